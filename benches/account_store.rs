@@ -0,0 +1,63 @@
+//! Compares the account storage backends on generated input covering every client ID.
+
+use std::hint::black_box;
+
+use coding_test::{AccountStorage, CheckedDecimal, Engine, EngineConfig, EngineTransaction};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+
+const TRANSACTIONS: u32 = 1_000_000;
+
+/// Deposits and withdrawals spread pseudo-randomly over all 65,536 clients.
+fn generate() -> Vec<EngineTransaction> {
+    let amount = |value| {
+        CheckedDecimal::parse(value).unwrap_or_else(|e| unreachable!("invalid amount: {e}"))
+    };
+    let (deposit, withdrawal) = (amount("10.5"), amount("3.25"));
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..TRANSACTIONS)
+        .map(|tx| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let client = state as u16;
+            let result = if (state >> 16) % 100 < 60 {
+                EngineTransaction::deposit(client, tx, deposit)
+            } else {
+                EngineTransaction::withdrawal(client, tx, withdrawal)
+            };
+            result.unwrap_or_else(|e| unreachable!("invalid generated transaction: {e}"))
+        })
+        .collect()
+}
+
+fn bench_account_storage(c: &mut Criterion) {
+    let transactions = generate();
+    let mut group = c.benchmark_group("account_storage");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(u64::from(TRANSACTIONS)));
+
+    for (name, storage) in [
+        ("dense", AccountStorage::Dense),
+        ("map", AccountStorage::Map),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| {
+                    let mut config = EngineConfig::default();
+                    config.account_storage = storage;
+                    let mut engine = Engine::with_config(config);
+                    for tx in transactions {
+                        let _ = engine.process_transaction(tx);
+                    }
+                    black_box(engine.output_items().count())
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_account_storage);
+criterion_main!(benches);
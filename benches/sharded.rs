@@ -0,0 +1,73 @@
+//! Compares the single-threaded [`Engine`] with [`ShardedEngine`] on generated input.
+
+use std::hint::black_box;
+
+use coding_test::{CheckedDecimal, Engine, EngineConfig, EngineTransaction, ShardedEngine};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+
+const TRANSACTIONS: u32 = 1_000_000;
+const CLIENTS: u32 = 1_000;
+
+/// Deposits and withdrawals spread over every client, with a dispute and resolve every 100
+/// transactions.
+fn generate() -> Vec<EngineTransaction> {
+    let amount = |value| {
+        CheckedDecimal::parse(value).unwrap_or_else(|e| unreachable!("invalid amount: {e}"))
+    };
+    (0..TRANSACTIONS)
+        .map(|i| {
+            let client = (i % CLIENTS) as u16;
+            let result = match i % 100 {
+                0..=59 => EngineTransaction::deposit(client, i, amount("10.5")),
+                60..=97 => EngineTransaction::withdrawal(client, i, amount("3.25")),
+                98 => Ok(EngineTransaction::dispute(client, i - 98)),
+                _ => Ok(EngineTransaction::resolve(client, i - 99)),
+            };
+            result.unwrap_or_else(|e| unreachable!("invalid generated transaction: {e}"))
+        })
+        .collect()
+}
+
+fn bench_engines(c: &mut Criterion) {
+    let transactions = generate();
+    let mut group = c.benchmark_group("process");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(u64::from(TRANSACTIONS)));
+
+    group.bench_function("single", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| {
+                let mut engine = Engine::new();
+                for tx in transactions {
+                    let _ = engine.process_transaction(tx);
+                }
+                black_box(engine.output_items().count())
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    for threads in [2, 4, 8] {
+        group.bench_function(format!("sharded/{threads}"), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| {
+                    let mut engine = ShardedEngine::new(EngineConfig::default(), threads);
+                    for (tag, tx) in transactions.into_iter().enumerate() {
+                        engine.submit(tag as u64, tx);
+                    }
+                    let (engine, _) = engine
+                        .finish()
+                        .unwrap_or_else(|e| unreachable!("in-memory merge failed: {e}"));
+                    black_box(engine.output_items().count())
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_engines);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Writes a synthetic transaction CSV to stdout, for benchmarking the engine on large inputs.
+//!
+//! ```bash
+//! cargo run --release --example generate -- 100000000 > synthetic.csv
+//! ```
+//!
+//! The first argument is the number of rows (100 million by default). Transactions are spread
+//! pseudo-randomly over all 65,536 client IDs; the output is the same on every run.
+
+use std::io::{self, BufWriter, Write};
+
+fn main() -> io::Result<()> {
+    let rows: u64 = match std::env::args().nth(1) {
+        Some(arg) => arg
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => 100_000_000,
+    };
+
+    let mut w = BufWriter::new(io::stdout().lock());
+    writeln!(w, "type, client, tx, amount")?;
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    for tx in 0..rows {
+        // xorshift64, good enough to scatter clients and operations
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let client = state as u16;
+        let tx = tx as u32;
+        match (state >> 16) % 100 {
+            0..=59 => {
+                let (units, fraction) = (state % 1000, state % 10_000);
+                writeln!(w, "deposit, {client}, {tx}, {units}.{fraction:04}")?
+            }
+            60..=94 => writeln!(w, "withdrawal, {client}, {tx}, {}.5", state % 100)?,
+            // Reference an earlier transaction; most belong to another client and are rejected
+            95..=97 => writeln!(w, "dispute, {client}, {}, ", tx / 2)?,
+            _ => writeln!(w, "resolve, {client}, {}, ", tx / 2)?,
+        }
+    }
+    w.flush()
+}
@@ -0,0 +1,144 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder};
+
+use crate::error::Error;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A compression format for input and output streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression implied by a `.gz` or `.zst` file extension, if any.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detects the compression of a stream from its magic bytes, without consuming them.
+    ///
+    /// Only the bytes already buffered by the first read are inspected, which is always enough
+    /// for files but may not be for a pipe delivering fewer than four bytes at first.
+    pub fn detect<R: BufRead>(r: &mut R) -> Result<Self, Error> {
+        let header = r.fill_buf()?;
+        Ok(if header.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        })
+    }
+
+    /// Wraps `r` in a streaming decoder for this compression.
+    pub fn decoder<'a, R: BufRead + 'a>(self, r: R) -> Result<Box<dyn BufRead + 'a>, Error> {
+        Ok(match self {
+            Compression::None => Box::new(r),
+            // Concatenated gzip members are read as one stream, like `zcat` does
+            Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(r))),
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(r)?)),
+        })
+    }
+}
+
+/// A writer compressing its output. [`CompressWriter::finish`] must be called to write the end
+/// of the compressed stream.
+pub struct CompressWriter<W: Write> {
+    inner: Encoder<W>,
+}
+
+enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressWriter<W> {
+    pub fn new(w: W, compression: Compression) -> Result<Self, Error> {
+        let inner = match compression {
+            Compression::None => Encoder::None(w),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(w, 0)?),
+        };
+        Ok(CompressWriter { inner })
+    }
+
+    /// Writes the end of the compressed stream and returns the underlying writer.
+    pub fn finish(self) -> Result<W, Error> {
+        let w = match self.inner {
+            Encoder::None(w) => w,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(w)
+    }
+}
+
+impl<W: Write> Write for CompressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Encoder::None(w) => w.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Encoder::None(w) => w.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::CsvReader;
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let csv_data = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n";
+
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut w = CompressWriter::new(Vec::new(), compression).unwrap();
+            w.write_all(csv_data.as_bytes()).unwrap();
+            let compressed = w.finish().unwrap();
+
+            let mut r = compressed.as_slice();
+            assert_eq!(Compression::detect(&mut r).unwrap(), compression);
+            let r = compression.decoder(r).unwrap();
+            let mut reader = CsvReader::from_reader(r).unwrap();
+            let record = reader.read_next().unwrap().unwrap();
+            assert_eq!(record.tx, 1);
+            assert!(reader.read_next().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_compression_from_extension() {
+        assert_eq!(
+            Compression::from_extension(Path::new("drop.csv.gz")),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_extension(Path::new("drop.jsonl.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_extension(Path::new("drop.csv")), None);
+    }
+}
@@ -1,70 +1,105 @@
-use std::collections::{HashMap, hash_map::Entry};
+use serde::Serialize;
 
 use crate::{
-    engine::{Chargeback, Deposit, Dispute, Resolve, Withdrawal, checked_decimal::CheckedDecimal},
+    engine::{
+        Adjustment, Chargeback, Deposit, Dispute, Resolve, Withdrawal,
+        checked_decimal::CheckedDecimal,
+        config::EngineConfig,
+        ledger::{LedgerAccount, Posting, Postings},
+        policy::DisputeContext,
+        registry::{DisputeStatus, TransactionKind, TransactionRecord},
+        status::{AccountStatus, StatusReason},
+        timestamp::Timestamp,
+    },
     error::Error,
 };
+
+/// The state of a single client account.
+#[derive(Debug, Clone)]
 pub struct Account {
     balance: AccountBalance,
-    locked: bool,
-    deposit_map: HashMap<u32, DepositRecord>,
+    status: AccountStatus,
+    status_reason: Option<StatusReason>,
+    latest_timestamp: Option<Timestamp>,
 }
 
 impl Account {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Account {
             balance: AccountBalance::new(),
-            locked: false,
-            deposit_map: HashMap::new(),
+            status: AccountStatus::Active,
+            status_reason: None,
+            latest_timestamp: None,
         }
     }
 
+    /// The latest timestamp of the transactions accepted for the account, if any had one.
+    pub fn latest_timestamp(&self) -> Option<Timestamp> {
+        self.latest_timestamp
+    }
+
+    /// Records the timestamp of an accepted transaction.
+    pub(crate) fn observe_timestamp(&mut self, timestamp: Option<Timestamp>) {
+        self.latest_timestamp = self.latest_timestamp.max(timestamp);
+    }
+
+    /// Whether the account has been locked by a chargeback.
     pub fn locked(&self) -> bool {
-        self.locked
+        self.status == AccountStatus::Locked
+    }
+
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// Why the account is not active, or `None` if it is.
+    pub fn status_reason(&self) -> Option<&StatusReason> {
+        self.status_reason.as_ref()
+    }
+
+    fn set_status(&mut self, status: AccountStatus, reason: Option<StatusReason>) {
+        self.status = status;
+        self.status_reason = reason;
     }
 
     pub fn balance(&self) -> &AccountBalance {
         &self.balance
     }
 
-    pub fn deposit(&mut self, deposit: Deposit) -> Result<(), Error> {
-        self.add_deposit_record(&deposit)?;
-        self.balance
-            .mutate(|balance| {
-                balance.available = balance.available.checked_add(deposit.amount)?;
-                Ok(())
-            })
-            .inspect_err(|_| {
-                self.remove_deposit_record(deposit.transaction_id);
-            })?;
-        Ok(())
+    pub(crate) fn deposit(
+        &mut self,
+        Deposit { amount, .. }: &Deposit,
+        postings: &mut Postings,
+    ) -> Result<(), Error> {
+        let deposit = Posting::new(LedgerAccount::Cash, postings.available(), *amount);
+        self.post(postings, &[deposit])
     }
 
-    pub fn withdraw(&mut self, Withdrawal { amount }: Withdrawal) -> Result<(), Error> {
-        if self.balance.available < amount {
+    pub(crate) fn withdraw(
+        &mut self,
+        Withdrawal { amount, .. }: &Withdrawal,
+        postings: &mut Postings,
+    ) -> Result<(), Error> {
+        if self.balance.available < *amount {
             return Err(Error::InsufficientFunds);
         }
 
-        self.balance.mutate(|balance| {
-            balance.available = balance.available.checked_sub(amount)?;
-            Ok(())
-        })?;
-        Ok(())
+        let withdrawal = Posting::new(postings.available(), LedgerAccount::Cash, *amount);
+        self.post(postings, &[withdrawal])
     }
 
-    pub fn start_dispute(
+    pub(crate) fn start_dispute(
         &mut self,
-        Dispute {
+        &Dispute {
             original_transaction_id,
-        }: Dispute,
+            amount,
+        }: &Dispute,
+        record: &mut TransactionRecord,
+        config: &EngineConfig,
+        postings: &mut Postings,
     ) -> Result<(), Error> {
-        let record = self
-            .deposit_map
-            .get_mut(&original_transaction_id)
-            .ok_or(Error::TransactionNotFound(original_transaction_id))?;
-
-        if record.dispute_status == DisputeStatus::InProgress {
-            return Err(Error::DisputeAlreadyStarted(original_transaction_id));
+        if !record.disputable(config) {
+            return Err(Error::DisputeNotAllowed(original_transaction_id));
         }
 
         // Unlikely happens because we lock the account when there is a chargeback
@@ -72,137 +107,296 @@ impl Account {
             return Err(Error::DisputeNotAllowed(original_transaction_id));
         }
 
-        let deposit_amount = record.deposit_amount;
-
-        // If the available balance is less than the deposit amount, we cannot start a dispute
-        if self.balance.available < deposit_amount {
-            return Err(Error::InsufficientFunds);
+        // Without an amount, the whole part of the transaction not disputed yet is disputed
+        let undisputed = record.undisputed_amount()?;
+        if undisputed == CheckedDecimal::ZERO {
+            return Err(Error::DisputeAlreadyStarted(original_transaction_id));
+        }
+        let amount = amount.unwrap_or(undisputed);
+        if amount > undisputed {
+            return Err(Error::DisputeAmountExceeded(original_transaction_id));
         }
 
-        self.balance.mutate(|s| {
+        config.dispute_policy.check_dispute(&self.dispute_context(
+            original_transaction_id,
+            record,
+            amount,
+        ))?;
+
+        let hold = match record.kind {
             // available can be negative if the deposit was already withdrawn
-            s.available = s.available.checked_sub(deposit_amount)?;
-            s.held = s.held.checked_add(deposit_amount)?;
-            Ok(())
-        })?;
+            TransactionKind::Deposit => Posting::new(postings.available(), postings.held(), amount),
+            // The withdrawn funds are provisionally credited to held until the dispute is settled
+            TransactionKind::Withdrawal => {
+                Posting::new(LedgerAccount::Suspense, postings.held(), amount)
+            }
+        };
+        self.post(postings, &[hold])?;
 
+        record.disputed = record.disputed.checked_add(amount)?;
         record.dispute_status = DisputeStatus::InProgress;
 
         Ok(())
     }
 
-    pub fn resolve_dispute(
+    pub(crate) fn resolve_dispute(
         &mut self,
-        Resolve {
+        &Resolve {
             original_transaction_id,
-        }: Resolve,
+            amount,
+        }: &Resolve,
+        record: &mut TransactionRecord,
+        config: &EngineConfig,
+        postings: &mut Postings,
     ) -> Result<(), Error> {
-        let record = self
-            .deposit_map
-            .get_mut(&original_transaction_id)
-            .ok_or(Error::TransactionNotFound(original_transaction_id))?;
-
         if record.dispute_status != DisputeStatus::InProgress {
             return Err(Error::DisputeNotStarted(original_transaction_id));
         }
 
+        // Without an amount, every disputed part of the transaction is resolved
+        let amount = amount.unwrap_or(record.disputed);
+        if amount > record.disputed {
+            return Err(Error::DisputeAmountExceeded(original_transaction_id));
+        }
+
+        config.dispute_policy.check_resolve(&self.dispute_context(
+            original_transaction_id,
+            record,
+            amount,
+        ))?;
+
         // If there are insufficient holds to resolve the dispute, return an error
         // This is unlikely to happen, because the we only reduce the held amount when the dispute is resolved
-        // and the deducted amount is always equal to the deposit amount.
+        // and the deducted amount is always equal to the disputed amount.
         // Nevertheless, we check it to ensure the integrity of the account state.
-        if self.balance.held < record.deposit_amount {
+        if self.balance.held < amount {
             return Err(Error::InsufficientHoldsToResolveDispute);
         }
 
-        self.balance.mutate(|s| {
-            s.held = s.held.checked_sub(record.deposit_amount)?;
-            s.available = s.available.checked_add(record.deposit_amount)?;
-            Ok(())
-        })?;
+        let release = match record.kind {
+            // The deposit stays with the client
+            TransactionKind::Deposit => Posting::new(postings.held(), postings.available(), amount),
+            // The withdrawal stands, the provisional credit is reverted
+            TransactionKind::Withdrawal => {
+                Posting::new(postings.held(), LedgerAccount::Suspense, amount)
+            }
+        };
+        self.post(postings, &[release])?;
 
-        record.dispute_status = DisputeStatus::NotStarted;
+        record.disputed = record.disputed.checked_sub(amount)?;
+        record.resolved = true;
+        record.settle();
         Ok(())
     }
 
-    pub fn chargeback(
+    pub(crate) fn chargeback(
         &mut self,
-        Chargeback {
+        &Chargeback {
             original_transaction_id,
-        }: Chargeback,
+            amount,
+        }: &Chargeback,
+        record: Option<&mut TransactionRecord>,
+        config: &EngineConfig,
+        postings: &mut Postings,
     ) -> Result<(), Error> {
-        let record = self.deposit_map.get_mut(&original_transaction_id);
-
         let record = match record {
             Some(record) => record,
             None => {
-                // Unable to find the deposit record
-                // This should be a data error from our partner, we lock the account without changing the holds and available amounts
-                self.locked = true;
+                // Unable to find the transaction record
+                // This should be a data error from our partner, by default we lock the account without changing the holds and available amounts
+                config
+                    .dispute_policy
+                    .check_undisputed_chargeback(original_transaction_id, None)?;
+                self.set_status(AccountStatus::Locked, Some(StatusReason::UnknownChargeback));
                 return Ok(());
             }
         };
 
+        if !record.disputable(config) {
+            return Err(Error::DisputeNotAllowed(original_transaction_id));
+        }
+
         match record.dispute_status {
-            DisputeStatus::NotStarted => {
+            DisputeStatus::NotStarted | DisputeStatus::Resolved => {
                 // The client filed a chargeback without opening a dispute
-                // We lock the account without changing the holds and available amounts
-                self.locked = true;
+                // By default we lock the account without changing the holds and available amounts
+                let undisputed = record.undisputed_amount()?;
+                let amount = amount.unwrap_or(undisputed);
+                if amount > undisputed {
+                    return Err(Error::DisputeAmountExceeded(original_transaction_id));
+                }
+                config.dispute_policy.check_undisputed_chargeback(
+                    original_transaction_id,
+                    Some(&self.dispute_context(original_transaction_id, record, amount)),
+                )?;
+                // The funds still leave the bank: a deposit is lost, a withdrawal comes back and
+                // waits for an operator to credit the client
+                let reversal = match record.kind {
+                    TransactionKind::Deposit => {
+                        Posting::new(LedgerAccount::ChargebackLosses, LedgerAccount::Cash, amount)
+                    }
+                    TransactionKind::Withdrawal => {
+                        Posting::new(LedgerAccount::Cash, LedgerAccount::Suspense, amount)
+                    }
+                };
+                self.post(postings, &[reversal])?;
+                self.set_status(
+                    AccountStatus::Locked,
+                    Some(StatusReason::UndisputedChargeback),
+                );
+                // The part charged back cannot be disputed or charged back again
+                record.charged_back = record.charged_back.checked_add(amount)?;
+                record.settle();
                 return Ok(());
             }
             DisputeStatus::InProgress => {}
             DisputeStatus::Chargebacked => {
-                return Err(Error::DispputeAlreadyChargedback(original_transaction_id));
+                return Err(Error::DisputeAlreadyChargedBack(original_transaction_id));
             }
         }
 
+        // Without an amount, every disputed part of the transaction is charged back
+        let amount = amount.unwrap_or(record.disputed);
+        if amount > record.disputed {
+            return Err(Error::DisputeAmountExceeded(original_transaction_id));
+        }
+
         // If the record is in dispute, we proceed with the chargeback
-        self.balance.mutate(|s| {
-            s.held = s.held.checked_sub(record.deposit_amount)?;
-            Ok(())
-        })?;
-        self.locked = true; // Lock the account after a chargeback
+        match record.kind {
+            // The deposited funds are reversed
+            TransactionKind::Deposit => {
+                let reversal = Posting::new(postings.held(), LedgerAccount::Cash, amount);
+                self.post(postings, &[reversal])?;
+            }
+            // The withdrawn funds come back from the partner and are returned to the client
+            TransactionKind::Withdrawal => {
+                let release = Posting::new(postings.held(), postings.available(), amount);
+                let reversal = Posting::new(LedgerAccount::Cash, LedgerAccount::Suspense, amount);
+                self.post(postings, &[release, reversal])?;
+            }
+        }
+        // Lock the account after a chargeback
+        self.set_status(AccountStatus::Locked, Some(StatusReason::Chargeback));
 
-        record.dispute_status = DisputeStatus::Chargebacked;
+        record.disputed = record.disputed.checked_sub(amount)?;
+        record.charged_back = record.charged_back.checked_add(amount)?;
+        // The rest of a partially charged back dispute stays open
+        record.settle();
         Ok(())
     }
 
-    pub fn clear_deposit_records(&mut self) {
-        self.deposit_map.clear();
+    /// Lifts the lock and the freeze of the account. The transaction records are kept, so
+    /// disputes can go on after it.
+    pub(crate) fn unlock(&mut self) {
+        self.set_status(AccountStatus::Active, None);
     }
 
-    fn add_deposit_record(&mut self, deposit: &Deposit) -> Result<(), Error> {
-        match self.deposit_map.entry(deposit.transaction_id) {
-            Entry::Occupied(_) => {
-                return Err(Error::DuplicateTransactionId(deposit.transaction_id));
-            }
-            Entry::Vacant(entry) => {
-                entry.insert(DepositRecord {
-                    deposit_amount: deposit.amount,
-                    dispute_status: DisputeStatus::NotStarted,
-                });
-            }
+    pub(crate) fn freeze(&mut self, reason: &str) {
+        self.set_status(
+            AccountStatus::Frozen,
+            Some(StatusReason::Operator(reason.to_string())),
+        );
+    }
+
+    /// Closes the account, which must not hold any funds.
+    pub(crate) fn close(&mut self, client_id: u16, reason: &str) -> Result<(), Error> {
+        if self.balance.available != CheckedDecimal::ZERO
+            || self.balance.held != CheckedDecimal::ZERO
+        {
+            return Err(Error::AccountNotEmpty(client_id));
         }
+        self.set_status(
+            AccountStatus::Closed,
+            Some(StatusReason::Operator(reason.to_string())),
+        );
         Ok(())
     }
 
-    fn remove_deposit_record(&mut self, transaction_id: u32) {
-        self.deposit_map.remove(&transaction_id);
+    pub(crate) fn adjust(
+        &mut self,
+        Adjustment { amount, .. }: &Adjustment,
+        postings: &mut Postings,
+    ) -> Result<(), Error> {
+        let adjustment = if amount.is_sign_negative() {
+            let amount = CheckedDecimal::ZERO.checked_sub(*amount)?;
+            // A debit cannot take more than the available funds
+            if self.balance.available < amount {
+                return Err(Error::InsufficientFunds);
+            }
+            Posting::new(postings.available(), LedgerAccount::Suspense, amount)
+        } else {
+            Posting::new(LedgerAccount::Suspense, postings.available(), *amount)
+        };
+        self.post(postings, &[adjustment])
+    }
+
+    /// Restores an account saved in a snapshot, from the postings bringing its balance.
+    pub(crate) fn restore(
+        client_id: u16,
+        postings: &[Posting],
+        status: AccountStatus,
+        status_reason: Option<StatusReason>,
+    ) -> Result<Self, Error> {
+        let mut account = Account::new();
+        account.apply_journaled(client_id, postings, status, status_reason)?;
+        Ok(account)
     }
-}
 
-struct DepositRecord {
-    deposit_amount: CheckedDecimal,
-    dispute_status: DisputeStatus,
-}
+    /// Applies a change read back from the journal.
+    pub(crate) fn apply_journaled(
+        &mut self,
+        client_id: u16,
+        postings: &[Posting],
+        status: AccountStatus,
+        status_reason: Option<StatusReason>,
+    ) -> Result<(), Error> {
+        self.balance.mutate(|balance| {
+            postings
+                .iter()
+                .try_for_each(|posting| balance.post(client_id, posting))
+        })?;
+        self.set_status(status, status_reason);
+        Ok(())
+    }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DisputeStatus {
-    NotStarted,
-    InProgress,
-    Chargebacked,
+    /// Applies postings to the balance, all or none of them, and adds them to those of the
+    /// transaction.
+    fn post(&mut self, postings: &mut Postings, new: &[Posting]) -> Result<(), Error> {
+        let client_id = postings.client_id();
+        self.balance.mutate(|balance| {
+            new.iter()
+                .try_for_each(|posting| balance.post(client_id, posting))
+        })?;
+        postings.extend(new);
+        Ok(())
+    }
+
+    fn dispute_context<'a>(
+        &'a self,
+        transaction_id: u32,
+        record: &TransactionRecord,
+        amount: CheckedDecimal,
+    ) -> DisputeContext<'a> {
+        DisputeContext {
+            transaction_id,
+            kind: record.kind,
+            amount,
+            transaction_amount: record.amount,
+            disputed: record.disputed,
+            charged_back: record.charged_back,
+            resolved: record.resolved,
+            status: record.dispute_status,
+            balance: &self.balance,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// The funds of an account. `computed_total` is always `available + held`.
+///
+/// The balance is the sum of the postings to the client's accounts of the
+/// [`Ledger`](crate::Ledger): `available` and `held` are the credit balances of the available and
+/// held accounts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct AccountBalance {
     pub available: CheckedDecimal,
     pub held: CheckedDecimal,
@@ -210,7 +404,7 @@ pub struct AccountBalance {
 }
 
 impl AccountBalance {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         AccountBalance {
             available: CheckedDecimal::ZERO,
             held: CheckedDecimal::ZERO,
@@ -218,6 +412,29 @@ impl AccountBalance {
         }
     }
 
+    /// Applies the legs of a posting which fall on the accounts of `client_id`.
+    fn post(&mut self, client_id: u16, posting: &Posting) -> Result<(), Error> {
+        match posting.debit {
+            LedgerAccount::Available(client) if client == client_id => {
+                self.available = self.available.checked_sub(posting.amount)?;
+            }
+            LedgerAccount::Held(client) if client == client_id => {
+                self.held = self.held.checked_sub(posting.amount)?;
+            }
+            _ => {}
+        }
+        match posting.credit {
+            LedgerAccount::Available(client) if client == client_id => {
+                self.available = self.available.checked_add(posting.amount)?;
+            }
+            LedgerAccount::Held(client) if client == client_id => {
+                self.held = self.held.checked_add(posting.amount)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     fn mutate<F>(&mut self, mutator: F) -> Result<(), Error>
     where
         F: FnOnce(&mut Self) -> Result<(), Error>,
@@ -226,17 +443,17 @@ impl AccountBalance {
         match mutator(self) {
             Ok(()) => {
                 // Recalculate the total after mutation
-                if self.available != snapshot.available
-                    || self.held != snapshot.held
-                {
+                if self.available != snapshot.available || self.held != snapshot.held {
                     match self.available.checked_add(self.held) {
                         Ok(total) => self.computed_total = total,
                         Err(err) => {
                             *self = snapshot; // Rollback to the previous state
-                            return Err(Error::InvalidTotalAmount { source: Box::new(err) });
-                        },
+                            return Err(Error::InvalidTotalAmount {
+                                source: Box::new(err),
+                            });
+                        }
                     }
-                }                
+                }
                 Ok(())
             }
             Err(err) => {
@@ -247,7 +464,6 @@ impl AccountBalance {
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,26 +476,29 @@ mod tests {
         balance.held = decimal("50.00");
 
         // Successful mutation
-        balance.mutate(|b| {
-            b.available = b.available.checked_add(decimal("20.00"))?;
-            Ok(())
-        }).unwrap();
+        balance
+            .mutate(|b| {
+                b.available = b.available.checked_add(decimal("20.00"))?;
+                Ok(())
+            })
+            .unwrap();
 
         assert_eq!(balance.available, decimal("120.00"));
         assert_eq!(balance.held, decimal("50.00"));
         assert_eq!(balance.computed_total, decimal("170.00"));
 
         // Failed mutation
-        balance.mutate(|b| {
-            b.available = b.available.checked_sub(decimal("200.00"))?;
-            b.held = b.held.checked_add(decimal_max())?; // This will cause an overflow
-            Ok(())
-        }).unwrap_err();
+        balance
+            .mutate(|b| {
+                b.available = b.available.checked_sub(decimal("200.00"))?;
+                b.held = b.held.checked_add(decimal_max())?; // This will cause an overflow
+                Ok(())
+            })
+            .unwrap_err();
 
         // Ensure the balance is rolled back to the previous state
         assert_eq!(balance.available, decimal("120.00"));
         assert_eq!(balance.held, decimal("50.00"));
         assert_eq!(balance.computed_total, decimal("170.00"));
     }
-
-}
\ No newline at end of file
+}
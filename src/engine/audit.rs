@@ -0,0 +1,343 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        AccountBalance, AccountStatus, DisputeStatus, StatusReason, csv::TransactionType,
+        timestamp::Timestamp,
+    },
+    error::Error,
+};
+
+/// Receives an [`AuditEvent`] for every transaction accepted by an [`Engine`](crate::Engine),
+/// see [`Engine::set_audit_sink`](crate::Engine::set_audit_sink).
+///
+/// Events are recorded after the change is applied, so a transaction that fails to apply is
+/// never audited. If recording fails, the transaction stays applied and
+/// [`Engine::process_transaction`](crate::Engine::process_transaction) returns
+/// [`Error::WriteAudit`]; the engine then has to be recovered from its journal, which records
+/// the events after [`AuditSink::last_sequence`].
+pub trait AuditSink: Send {
+    fn record(&mut self, event: &AuditEvent) -> Result<(), Error>;
+
+    /// The sequence of the last event recorded, including by earlier runs, if any.
+    fn last_sequence(&self) -> Option<u64>;
+}
+
+/// The state transitions made by an accepted transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEvent {
+    /// The position of the transaction among those processed by the engine, from 0.
+    pub sequence: u64,
+    pub client: u16,
+    /// The ID of the transaction, as returned by
+    /// [`EngineTransaction::transaction_id`](crate::EngineTransaction::transaction_id).
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub timestamp: Option<Timestamp>,
+    /// The balance of the account before the transaction, zero for a new client.
+    pub before: AccountBalance,
+    pub after: AccountBalance,
+    /// The dispute status of the referenced transaction before and after a dispute, resolve or
+    /// chargeback. `None` for other transactions and chargebacks of unknown transactions.
+    pub dispute_status: Option<Transition<DisputeStatus>>,
+    /// The status of the account before and after, if the transaction changed it, e.g. when a
+    /// chargeback locked the account.
+    pub status: Option<Transition<AccountStatus>>,
+    /// Why the account is not active after the transaction.
+    pub status_reason: Option<StatusReason>,
+}
+
+/// A value before and after a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Transition<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// Appends every event to a file, one JSON object per line.
+///
+/// Each event is handed to the OS in a single write, so the events recorded survive the process
+/// dying.
+pub struct FileAuditSink {
+    file: File,
+    last_sequence: Option<u64>,
+}
+
+/// The part of a recorded event read back when the file is opened again.
+#[derive(Deserialize)]
+struct RecordedEvent {
+    sequence: u64,
+}
+
+impl FileAuditSink {
+    /// Opens the file at `path` for appending, creating it if it does not exist, and reads the
+    /// sequence of the last event it contains.
+    ///
+    /// An incomplete last line, left behind when the process died while appending, is discarded.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path.as_ref())?;
+        let (complete_len, line) = last_line(&mut file)?;
+        file.set_len(complete_len)?;
+        let last_sequence = if line.is_empty() {
+            None
+        } else {
+            let event: RecordedEvent = serde_json::from_slice(&line).map_err(io::Error::from)?;
+            Some(event.sequence)
+        };
+        Ok(FileAuditSink {
+            file,
+            last_sequence,
+        })
+    }
+}
+
+/// Returns the length of `file` up to the end of its last complete line, and that line.
+///
+/// The file is read backwards from its end, so that opening a large log stays cheap.
+fn last_line(file: &mut File) -> io::Result<(u64, Vec<u8>)> {
+    let mut start = file.seek(SeekFrom::End(0))?;
+    // The bytes from `start` to the end of the file
+    let mut tail = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        match tail.iter().rposition(|&byte| byte == b'\n') {
+            Some(newline) => {
+                let line_start = tail[..newline]
+                    .iter()
+                    .rposition(|&byte| byte == b'\n')
+                    .map(|previous| previous + 1)
+                    .or((start == 0).then_some(0));
+                if let Some(line_start) = line_start {
+                    let end = newline + 1;
+                    return Ok((start + end as u64, tail[line_start..end].to_vec()));
+                }
+            }
+            None if start == 0 => return Ok((0, Vec::new())),
+            None => {}
+        }
+        let n = start.min(chunk.len() as u64) as usize;
+        start -= n as u64;
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk[..n])?;
+        tail.splice(0..0, chunk[..n].iter().copied());
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&mut self, event: &AuditEvent) -> Result<(), Error> {
+        let mut buf = serde_json::to_vec(event).map_err(Error::WriteJsonRecord)?;
+        buf.push(b'\n');
+        self.file.write_all(&buf)?;
+        self.last_sequence = Some(event.sequence);
+        Ok(())
+    }
+
+    fn last_sequence(&self) -> Option<u64> {
+        self.last_sequence
+    }
+}
+
+/// Keeps the events in memory, e.g. for tests.
+///
+/// Clones share the same events, so a clone can be handed to the engine and the events read
+/// from the original.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryAuditSink {
+    events: Arc<Mutex<Vec<AuditEvent>>>,
+}
+
+impl MemoryAuditSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The events recorded so far, in order.
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn record(&mut self, event: &AuditEvent) -> Result<(), Error> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event.clone());
+        Ok(())
+    }
+
+    fn last_sequence(&self) -> Option<u64> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .last()
+            .map(|event| event.sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineTransaction, test_utils::*};
+
+    #[test]
+    fn test_audit_events() {
+        let sink = MemoryAuditSink::new();
+        let mut engine = Engine::new();
+        engine.set_audit_sink(Box::new(sink.clone()));
+        engine.process_transaction(deposit(1, 1, "10.0")).unwrap();
+        engine.process_transaction(dispute(1, 1)).unwrap();
+        // Rejected transactions are not audited
+        engine
+            .process_transaction(withdrawal(1, 2, "5.0"))
+            .unwrap_err();
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+
+        let events = sink.events();
+        assert_eq!(events.len(), 3);
+
+        let deposit = &events[0];
+        assert_eq!((deposit.sequence, deposit.tx), (0, 1));
+        assert_eq!(deposit.before.computed_total, decimal("0"));
+        assert_eq!(deposit.after.available, decimal("10.0"));
+        assert_eq!(deposit.dispute_status, None);
+        assert_eq!(deposit.status, None);
+
+        let dispute = &events[1];
+        assert_eq!(dispute.before.held, decimal("0"));
+        assert_eq!(dispute.after.held, decimal("10.0"));
+        assert_eq!(
+            dispute.dispute_status,
+            Some(Transition {
+                from: DisputeStatus::NotStarted,
+                to: DisputeStatus::InProgress,
+            })
+        );
+
+        let chargeback = &events[2];
+        assert_eq!(chargeback.sequence, 3);
+        assert_eq!(chargeback.after.computed_total, decimal("0"));
+        assert_eq!(
+            chargeback.dispute_status,
+            Some(Transition {
+                from: DisputeStatus::InProgress,
+                to: DisputeStatus::Chargebacked,
+            })
+        );
+        assert_eq!(
+            chargeback.status,
+            Some(Transition {
+                from: AccountStatus::Active,
+                to: AccountStatus::Locked,
+            })
+        );
+        assert_eq!(chargeback.status_reason, Some(StatusReason::Chargeback));
+    }
+
+    #[test]
+    fn test_file_audit_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+
+        let mut engine = Engine::new();
+        engine.set_audit_sink(Box::new(FileAuditSink::open(&path).unwrap()));
+        engine.process_transaction(deposit(1, 1, "10.0")).unwrap();
+        engine.process_transaction(chargeback(1, 9)).unwrap();
+        drop(engine);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"sequence":0,"client":1,"tx":1,"type":"deposit","timestamp":null,"before":{"available":"0","held":"0","computed_total":"0"},"after":{"available":"10.0","held":"0","computed_total":"10.0"},"dispute_status":null,"status":null,"status_reason":null}"#,
+                r#"{"sequence":1,"client":1,"tx":9,"type":"chargeback","timestamp":null,"before":{"available":"10.0","held":"0","computed_total":"10.0"},"after":{"available":"10.0","held":"0","computed_total":"10.0"},"dispute_status":null,"status":{"from":"active","to":"locked"},"status_reason":"unknown_chargeback"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audit_recovered_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        let audit = dir.path().join("audit.jsonl");
+        let run = |transactions: Vec<EngineTransaction>| {
+            let mut engine = Engine::new();
+            engine.set_audit_sink(Box::new(FileAuditSink::open(&audit).unwrap()));
+            engine.attach_journal(&journal).unwrap();
+            for transaction in transactions {
+                engine.process_transaction(transaction).unwrap();
+            }
+        };
+        run(vec![
+            deposit(1, 1, "10.0"),
+            dispute(1, 1),
+            deposit(2, 2, "5.0"),
+        ]);
+        let contents = std::fs::read_to_string(&audit).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+
+        // The process died after applying the last two transactions, while auditing the first
+        let first_line = contents.lines().next().unwrap();
+        std::fs::write(&audit, format!("{first_line}\n{{\"sequence\":1,")).unwrap();
+        run(vec![]);
+        assert_eq!(std::fs::read_to_string(&audit).unwrap(), contents);
+
+        // Nothing is missing, so nothing is audited again
+        run(vec![resolve(1, 1)]);
+        let lines = std::fs::read_to_string(&audit).unwrap();
+        let lines: Vec<_> = lines.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[3].starts_with(r#"{"sequence":3,"client":1,"tx":1,"type":"resolve""#));
+    }
+
+    struct FailingSink;
+
+    impl AuditSink for FailingSink {
+        fn record(&mut self, _event: &AuditEvent) -> Result<(), Error> {
+            Err(Error::Io(io::Error::other("disk full")))
+        }
+
+        fn last_sequence(&self) -> Option<u64> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_audit_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let mut engine = Engine::recover(&path).unwrap();
+        engine.set_audit_sink(Box::new(FailingSink));
+
+        // The deposit is applied, but the engine stops since its event is missing
+        let result = engine.process_transaction(deposit(1, 1, "10.0"));
+        assert!(matches!(result, Err(Error::WriteAudit(_))));
+        assert_eq!(get_client_output(&engine, 1).available, decimal("10.0"));
+        let result = engine.process_transaction(deposit(2, 2, "5.0"));
+        assert!(matches!(result, Err(Error::EngineFailed)));
+        drop(engine);
+
+        // Recovering audits the deposit
+        let sink = MemoryAuditSink::new();
+        let mut engine = Engine::new();
+        engine.set_audit_sink(Box::new(sink.clone()));
+        engine.attach_journal(&path).unwrap();
+        let events = sink.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].sequence, events[0].tx), (0, 1));
+        assert_eq!(events[0].after.available, decimal("10.0"));
+    }
+}
@@ -1,25 +1,24 @@
 use std::fmt;
 
 use rust_decimal::Decimal;
-use serde::{Serialize};
+use serde::{Deserialize, Serialize};
 
 use crate::error::Error;
 
 /// A helper type for checked decimal operations to ensure error handling and prevent panic on overflow/underflow.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct CheckedDecimal(Decimal);
 
 impl CheckedDecimal {
     pub const ZERO: Self = CheckedDecimal(Decimal::ZERO);
+    pub(crate) const MAX: Self = CheckedDecimal(Decimal::MAX);
     const PRECISION: u32 = 4;
 
-    // Creates a new `CheckedDecimal` from a string, rounding to the defined precision.
+    /// Creates a new `CheckedDecimal` from a string, rounding to the defined precision.
     pub fn parse(value: &str) -> Result<Self, Error> {
         Decimal::from_str_exact(value)
-            .map(|v| {
-                CheckedDecimal(v.round_dp(Self::PRECISION))
-            })
+            .map(|v| CheckedDecimal(v.round_dp(Self::PRECISION)))
             .map_err(Error::ParseDecimal)
     }
 
@@ -28,11 +27,22 @@ impl CheckedDecimal {
     }
 
     pub fn checked_add(self, other: CheckedDecimal) -> Result<Self, Error> {
-        self.0.checked_add(other.0).map(CheckedDecimal).ok_or(Error::DecimalOverflow)
+        self.0
+            .checked_add(other.0)
+            .map(CheckedDecimal)
+            .ok_or(Error::DecimalOverflow)
+    }
+
+    /// Adds `other`, or returns [`CheckedDecimal::MAX`] on overflow.
+    pub(crate) fn saturating_add(self, other: CheckedDecimal) -> Self {
+        CheckedDecimal(self.0.saturating_add(other.0))
     }
 
     pub fn checked_sub(self, other: CheckedDecimal) -> Result<Self, Error> {
-        self.0.checked_sub(other.0).map(CheckedDecimal).ok_or(Error::DecimalUnderflow)
+        self.0
+            .checked_sub(other.0)
+            .map(CheckedDecimal)
+            .ok_or(Error::DecimalUnderflow)
     }
 }
 
@@ -53,4 +63,9 @@ impl From<Decimal> for CheckedDecimal {
     fn from(value: Decimal) -> Self {
         CheckedDecimal(value.round_dp(Self::PRECISION))
     }
-}
\ No newline at end of file
+}
+impl From<CheckedDecimal> for Decimal {
+    fn from(value: CheckedDecimal) -> Self {
+        value.0
+    }
+}
@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::engine::ledger::LedgerEntries;
+use crate::engine::policy::{DefaultDisputePolicy, DisputePolicy};
+
+/// Settings that change how the [`Engine`](crate::Engine) applies transactions.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EngineConfig {
+    /// Whether disputes, resolves and chargebacks may reference withdrawals.
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// The rules consulted for disputes, resolves and chargebacks.
+    pub dispute_policy: Arc<dyn DisputePolicy>,
+    /// How accounts are stored.
+    pub account_storage: AccountStorage,
+    /// How long deposits and withdrawals can be disputed after they are accepted. `None`, the
+    /// default, keeps them disputable forever.
+    pub dispute_window: Option<DisputeWindow>,
+    /// Whether a transaction may be earlier than a previous transaction of the same client.
+    pub out_of_order_timestamps: OutOfOrderTimestamps,
+    /// Whether the admin operations (unlock, freeze, close and adjustment) are accepted.
+    pub admin_operations: AdminOperations,
+    /// Whether the ledger keeps every posting, or only the balances of its accounts.
+    pub ledger_entries: LedgerEntries,
+    /// Whether the accepted transactions of every account are kept, for statements.
+    pub account_history: AccountHistory,
+    /// When the entries of the journal are synced to the disk.
+    pub journal_sync: JournalSync,
+    /// How the transactions of a client are found by
+    /// [`Engine::client_transactions`](crate::Engine::client_transactions).
+    pub client_transactions: ClientTransactions,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::default(),
+            dispute_policy: Arc::new(DefaultDisputePolicy),
+            account_storage: AccountStorage::default(),
+            dispute_window: None,
+            out_of_order_timestamps: OutOfOrderTimestamps::default(),
+            admin_operations: AdminOperations::default(),
+            ledger_entries: LedgerEntries::default(),
+            account_history: AccountHistory::default(),
+            journal_sync: JournalSync::default(),
+            client_transactions: ClientTransactions::default(),
+        }
+    }
+}
+
+/// How disputes against withdrawals are handled.
+///
+/// When allowed, a disputed withdrawal is provisionally credited to the held funds. Resolving
+/// the dispute reverts the credit, while a chargeback moves it to the available funds and
+/// locks the account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithdrawalDisputes {
+    /// Disputes can only be filed against deposits.
+    #[default]
+    Rejected,
+    /// Disputes can be filed against deposits and withdrawals.
+    Allowed,
+}
+
+/// The data structure holding the accounts. Both iterate in client ID order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AccountStorage {
+    /// A vector with a pre-allocated 96-byte slot for each of the 65,536 client IDs (6 MiB, for
+    /// each worker of a [`ShardedEngine`](crate::ShardedEngine)). Lookups are a plain index.
+    #[default]
+    Dense,
+    /// An ordered map only holding the clients seen. Uses less memory for few clients, but
+    /// every lookup walks the tree.
+    ///
+    /// It is a B-tree rather than a hash map because accounts are written in client ID order:
+    /// a hash map would have to sort every client on output, while a lookup in a B-tree of at
+    /// most 65,536 clients only visits a few nodes.
+    Map,
+}
+
+/// How long a deposit or withdrawal can be disputed after it was accepted.
+///
+/// Once the window has passed, the transaction's record is evicted: disputes and chargebacks
+/// against it are rejected with [`Error::DisputeWindowExpired`](crate::Error::DisputeWindowExpired).
+/// A dispute opened within the window can still be resolved or charged back after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DisputeWindow {
+    /// The transaction can be disputed by this many subsequent transactions, accepted or
+    /// rejected, of any client.
+    Transactions(u64),
+    /// The transaction can be disputed by transactions at most this much later than its
+    /// timestamp.
+    ///
+    /// Records are evicted once a later transaction is accepted. A transaction without a
+    /// timestamp is taken to be as late as the latest timestamp accepted; until one is, records
+    /// do not expire.
+    ///
+    /// The clock is shared by all clients: a single accepted transaction timestamped far in the
+    /// future evicts the records of every client at once. Timestamps are not checked against
+    /// the current time, so they must come from a trusted source.
+    Duration(Duration),
+}
+
+/// How transactions earlier than a previous transaction of the same client are handled.
+///
+/// Only transactions with a timestamp are compared, against the latest timestamp accepted for
+/// the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutOfOrderTimestamps {
+    /// They are processed like any other transaction.
+    #[default]
+    Allowed,
+    /// They are rejected with [`Error::TimestampOutOfOrder`](crate::Error::TimestampOutOfOrder).
+    Rejected,
+}
+
+/// Whether admin operations are accepted.
+///
+/// They are meant for operators, e.g. to unlock an account after a chargeback was reviewed, and
+/// are rejected by default so that a partner's input cannot carry them. Every accepted admin
+/// operation is recorded with its reason, see [`Engine::admin_actions`](crate::Engine::admin_actions).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AdminOperations {
+    /// They are rejected with
+    /// [`Error::AdminOperationsDisabled`](crate::Error::AdminOperationsDisabled).
+    #[default]
+    Rejected,
+    /// They are applied like any other transaction.
+    Allowed,
+}
+
+/// Whether the engine keeps the history of every account.
+///
+/// The history lists the accepted transactions of a client in order, each with the balance and
+/// status it left, see [`Engine::history`](crate::Engine::history). It is kept in snapshots and
+/// rebuilt from the journal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountHistory {
+    /// Only the current state of the accounts is kept.
+    #[default]
+    Discarded,
+    /// Every accepted transaction is kept. Uses memory for each accepted transaction.
+    Kept,
+}
+
+/// When the entries of the [journal](crate::Engine::attach_journal) are synced to the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JournalSync {
+    /// Each entry is handed to the OS in a single write. It survives the process dying, but can
+    /// be lost on a power loss or an OS crash.
+    #[default]
+    Deferred,
+    /// The data of each entry is synced to the disk before the change is applied, so it
+    /// survives a power loss. Every transaction waits for the disk.
+    EveryEntry,
+}
+
+/// How [`Engine::client_transactions`](crate::Engine::client_transactions) finds the transactions
+/// of a client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientTransactions {
+    /// Every record is visited, which reads the whole file of a
+    /// [`DiskRecordStore`](crate::DiskRecordStore).
+    #[default]
+    Scanned,
+    /// The transaction IDs of every client are indexed, so only the records of the client are
+    /// read. Uses memory for each record.
+    Indexed,
+}
@@ -1,53 +1,129 @@
 use std::io::Read;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{engine::source::TransactionSource, error::Error};
 
+/// The columns of a header row, of which the first four are required.
+pub(crate) const COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "reason"];
+
+/// Reads transaction records from CSV input with a `type, client, tx, amount` header, optionally
+/// followed by a `timestamp` and a `reason` column.
 pub struct CsvReader<R: Read> {
     reader: csv::Reader<R>,
     record: csv::StringRecord,
+    /// The number of columns of the header, which every row must have.
+    columns: usize,
+    line: u64,
 }
 
 impl<R: Read> CsvReader<R> {
+    /// Reads the header of `r`, and rejects optional columns that are not in order: records are
+    /// read by position, so a `reason` column needs a `timestamp` column before it.
+    ///
+    /// Rows with a different number of fields than the header are rejected one by one with
+    /// [`Error::CsvFieldCount`], rather than stopping the input.
     pub fn from_reader(r: R) -> Result<Self, Error> {
-        let reader = csv::ReaderBuilder::new()
+        let mut reader = csv::ReaderBuilder::new()
             .trim(csv::Trim::All)
+            .flexible(true)
             .from_reader(r);
+        let headers = reader.headers().map_err(Error::read_csv)?;
+        if let Some((name, _)) = headers
+            .iter()
+            .zip(COLUMNS)
+            .skip(4)
+            .find(|(name, column)| name != column)
+        {
+            return Err(Error::UnexpectedCsvColumn(name.to_string()));
+        }
+        let columns = headers.len();
 
         Ok(CsvReader {
             reader,
             record: csv::StringRecord::new(),
+            columns,
+            line: 0,
         })
     }
 
-    pub fn read_next(&mut self) -> Result<Option<CsvInputRecord>, Error> {
-        let ok = self
-            .reader
-            .read_record(&mut self.record)
-            .map_err(Error::ReadCsvRecord)?;
+    pub fn read_next(&mut self) -> Result<Option<CsvInputRecord<'_>>, Error> {
+        let ok = match self.reader.read_record(&mut self.record) {
+            Ok(ok) => ok,
+            Err(e) => {
+                // A row with invalid UTF-8 is cleared, so its line is only known from the error
+                self.line = e.position().map_or(0, csv::Position::line);
+                return Err(Error::read_csv(e));
+            }
+        };
         if !ok {
             return Ok(None);
         }
+        self.line = self.record.position().map_or(0, csv::Position::line);
+        if self.record.len() != self.columns {
+            return Err(Error::CsvFieldCount {
+                expected: self.columns,
+                found: self.record.len(),
+            });
+        }
 
-        let record: CsvInputRecord = self
+        let mut record: CsvInputRecord = self
             .record
             .deserialize(None)
             .map_err(Error::DeserializeCsvRecord)?;
+        record.line = self.line();
 
         Ok(Some(record))
     }
+
+    /// The line number of the last record read, starting at 1 for the header.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// The raw fields of the last record read, even if it could not be deserialized.
+    pub fn raw_fields(&self) -> impl Iterator<Item = &str> {
+        self.record.iter()
+    }
 }
 
+/// A raw input record, before semantic validation by [`EngineTransaction::parse_csv_record`].
+///
+/// Every [`TransactionSource`] produces these, whatever its format.
+///
+/// [`EngineTransaction::parse_csv_record`]: crate::EngineTransaction::parse_csv_record
 #[derive(Debug, Deserialize)]
 pub struct CsvInputRecord<'a> {
     pub r#type: TransactionType,
     pub client: u16,
     pub tx: u32,
     pub amount: Option<&'a str>,
+    /// An RFC 3339 date and time, or milliseconds since the epoch.
+    #[serde(default)]
+    pub timestamp: Option<&'a str>,
+    /// Why an admin operation was made.
+    #[serde(default)]
+    pub reason: Option<&'a str>,
+    /// The line the record was read from. For CSV input, the header is line 1.
+    #[serde(skip)]
+    pub line: u64,
+}
+
+impl<R: Read> TransactionSource for CsvReader<R> {
+    fn read_next(&mut self) -> Result<Option<CsvInputRecord<'_>>, Error> {
+        CsvReader::read_next(self)
+    }
+
+    fn line(&self) -> u64 {
+        CsvReader::line(self)
+    }
+
+    fn raw_fields(&self) -> Vec<String> {
+        CsvReader::raw_fields(self).map(str::to_string).collect()
+    }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -55,4 +131,41 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Lifts the lock or freeze of an account. An admin operation.
+    Unlock,
+    /// Rejects the client's transactions until the account is unlocked. An admin operation.
+    Freeze,
+    /// Permanently closes an empty account. An admin operation.
+    Close,
+    /// Credits or debits the available funds by a signed amount. An admin operation.
+    Adjustment,
+}
+
+impl TransactionType {
+    /// The name of the type as it appears in the `type` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Close => "close",
+            TransactionType::Adjustment => "adjustment",
+        }
+    }
+
+    /// Whether this is an admin operation, only accepted when
+    /// [`EngineConfig::admin_operations`](crate::EngineConfig::admin_operations) allows them.
+    pub fn is_admin(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock
+                | TransactionType::Freeze
+                | TransactionType::Close
+                | TransactionType::Adjustment
+        )
+    }
 }
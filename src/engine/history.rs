@@ -0,0 +1,261 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        AccountStatus, Engine, checked_decimal::CheckedDecimal, csv::TransactionType,
+        timestamp::Timestamp,
+    },
+    error::Error,
+};
+
+/// An accepted transaction in the history of an account, with the balance it left, see
+/// [`Engine::history`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// The position of the transaction among those processed by the engine, from 0. With
+    /// several worker threads, among those processed by the worker of the client.
+    pub sequence: u64,
+    pub client: u16,
+    /// The ID of the transaction, as returned by
+    /// [`EngineTransaction::transaction_id`](crate::EngineTransaction::transaction_id).
+    pub tx: u32,
+    pub r#type: TransactionType,
+    /// The amount of the transaction. For disputes, resolves and chargebacks without one, the
+    /// amount they applied to; `None` for a chargeback of an unknown transaction.
+    pub amount: Option<CheckedDecimal>,
+    pub available: CheckedDecimal,
+    pub held: CheckedDecimal,
+    pub total: CheckedDecimal,
+    pub status: AccountStatus,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// How statements are written by [`write_statement`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum StatementFormat {
+    /// CSV with a `sequence, client, tx, type, amount, available, held, total, status,
+    /// timestamp` header, one row per transaction of every client.
+    #[default]
+    Csv,
+    /// A table per client, followed by its closing balance.
+    Text,
+}
+
+/// Writes a running-balance statement of each of `clients`, from the history the engine kept.
+///
+/// Fails with [`Error::AccountNotFound`] if a client has no account. The statement only lists
+/// the transactions accepted while
+/// [`EngineConfig::account_history`](crate::EngineConfig::account_history) kept them.
+pub fn write_statement<W: Write>(
+    mut w: W,
+    format: StatementFormat,
+    engine: &Engine,
+    clients: &[u16],
+) -> Result<(), Error> {
+    let mut accounts = Vec::new();
+    for &client_id in clients {
+        let account = engine
+            .output_item(client_id)
+            .ok_or(Error::AccountNotFound(client_id))?;
+        accounts.push((account, engine.history(client_id)));
+    }
+
+    match format {
+        StatementFormat::Csv => {
+            let mut w = csv::Writer::from_writer(w);
+            for (_, history) in &accounts {
+                for entry in *history {
+                    w.serialize(entry).map_err(Error::WriteCsvRecord)?;
+                }
+            }
+            w.flush()?;
+        }
+        StatementFormat::Text => {
+            for (i, (account, history)) in accounts.iter().enumerate() {
+                if i > 0 {
+                    writeln!(w)?;
+                }
+                writeln!(w, "Statement of client {}", account.client)?;
+                let mut rows = vec![
+                    [
+                        "sequence",
+                        "tx",
+                        "type",
+                        "amount",
+                        "available",
+                        "held",
+                        "total",
+                        "status",
+                        "timestamp",
+                    ]
+                    .map(str::to_string),
+                ];
+                for entry in *history {
+                    rows.push([
+                        entry.sequence.to_string(),
+                        entry.tx.to_string(),
+                        entry.r#type.as_str().to_string(),
+                        entry
+                            .amount
+                            .map(|amount| amount.to_string())
+                            .unwrap_or_default(),
+                        entry.available.to_string(),
+                        entry.held.to_string(),
+                        entry.total.to_string(),
+                        entry.status.as_str().to_string(),
+                        entry
+                            .timestamp
+                            .map(|time| time.to_string())
+                            .unwrap_or_default(),
+                    ]);
+                }
+                let mut widths = [0; 9];
+                for row in &rows {
+                    for (width, cell) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(cell.len());
+                    }
+                }
+                for row in &rows {
+                    let cells: Vec<_> = row
+                        .iter()
+                        .zip(widths)
+                        .map(|(cell, width)| format!("{cell:<width$}"))
+                        .collect();
+                    writeln!(w, "{}", cells.join("  ").trim_end())?;
+                }
+                writeln!(
+                    w,
+                    "Closing balance: available {}, held {}, total {}, {}",
+                    account.available,
+                    account.held,
+                    account.total,
+                    account.status.as_str()
+                )?;
+            }
+            w.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{AccountHistory, EngineConfig, test_utils::*};
+
+    fn engine() -> Engine {
+        let mut engine = Engine::with_config(EngineConfig {
+            account_history: AccountHistory::Kept,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "10.0")).unwrap();
+        engine.process_transaction(deposit(2, 2, "3.0")).unwrap();
+        engine.process_transaction(withdrawal(1, 3, "4.0")).unwrap();
+        engine
+            .process_transaction(withdrawal(1, 4, "40.0"))
+            .unwrap_err();
+        engine.process_transaction(dispute(1, 1)).unwrap_err();
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+        engine
+    }
+
+    fn write(engine: &Engine, format: StatementFormat, clients: &[u16]) -> String {
+        let mut buf = Vec::new();
+        write_statement(&mut buf, format, engine, clients).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_history() {
+        let engine = engine();
+        // Rejected transactions are not part of the history
+        let history: Vec<_> = engine
+            .history(1)
+            .iter()
+            .map(|entry| {
+                (
+                    entry.tx,
+                    entry.r#type,
+                    entry.amount,
+                    entry.available,
+                    entry.status,
+                )
+            })
+            .collect();
+        let active = AccountStatus::Active;
+        assert_eq!(
+            history,
+            [
+                (
+                    1,
+                    TransactionType::Deposit,
+                    Some(decimal("10.0")),
+                    decimal("10.0"),
+                    active
+                ),
+                (
+                    3,
+                    TransactionType::Withdrawal,
+                    Some(decimal("4.0")),
+                    decimal("6.0"),
+                    active
+                ),
+                (
+                    1,
+                    TransactionType::Chargeback,
+                    Some(decimal("10.0")),
+                    decimal("6.0"),
+                    AccountStatus::Locked,
+                ),
+            ]
+        );
+        assert!(engine.history(3).is_empty());
+        assert!(Engine::new().history(1).is_empty());
+
+        // The history carries over a snapshot
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let config = EngineConfig {
+            account_history: AccountHistory::Kept,
+            ..Default::default()
+        };
+        let engine = Engine::load_snapshot_with_config(config, buf.as_slice()).unwrap();
+        assert_eq!(engine.history(1).len(), 3);
+        assert_eq!(engine.history(2).len(), 1);
+    }
+
+    #[test]
+    fn test_statement_formats() {
+        let engine = engine();
+        assert_eq!(
+            write(&engine, StatementFormat::Csv, &[2, 1]),
+            "sequence,client,tx,type,amount,available,held,total,status,timestamp\n\
+             1,2,2,deposit,3.0,3.0,0,3.0,active,\n\
+             0,1,1,deposit,10.0,10.0,0,10.0,active,\n\
+             2,1,3,withdrawal,4.0,6.0,0,6.0,active,\n\
+             5,1,1,chargeback,10.0,6.0,0,6.0,locked,\n"
+        );
+        assert_eq!(
+            write(&engine, StatementFormat::Text, &[1, 2]),
+            "Statement of client 1\n\
+             sequence  tx  type        amount  available  held  total  status  timestamp\n\
+             0         1   deposit     10.0    10.0       0     10.0   active\n\
+             2         3   withdrawal  4.0     6.0        0     6.0    active\n\
+             5         1   chargeback  10.0    6.0        0     6.0    locked\n\
+             Closing balance: available 6.0, held 0, total 6.0, locked\n\
+             \n\
+             Statement of client 2\n\
+             sequence  tx  type     amount  available  held  total  status  timestamp\n\
+             1         2   deposit  3.0     3.0        0     3.0    active\n\
+             Closing balance: available 3.0, held 0, total 3.0, active\n"
+        );
+
+        let mut buf = Vec::new();
+        let result = write_statement(&mut buf, StatementFormat::Csv, &engine, &[1, 9]);
+        assert!(matches!(result, Err(Error::AccountNotFound(9))));
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        AccountStatus, EngineTransaction, JournalSync, Posting, StatusReason, registry::RecordEntry,
+    },
+    error::Error,
+};
+
+/// An append-only log of every state change made by the engine, one JSON object per line.
+///
+/// Entries are written before the change is applied, so replaying the journal after a crash
+/// rebuilds the state the engine had when it stopped.
+pub struct Journal {
+    file: File,
+    sync: JournalSync,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it does not exist, and replays the entries it
+    /// already contains through `replay`.
+    ///
+    /// An incomplete last line, left behind when the process died while appending, is discarded.
+    pub fn open<F>(path: &Path, sync: JournalSync, mut replay: F) -> Result<Self, Error>
+    where
+        F: FnMut(JournalEntry) -> Result<(), Error>,
+    {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut reader = BufReader::new(&file);
+        let mut line = Vec::new();
+        let mut line_number = 0;
+        let mut complete_len = 0;
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)?;
+            if n == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            line_number += 1;
+            let entry = serde_json::from_slice(&line).map_err(|source| Error::ReadJournal {
+                line: line_number,
+                source,
+            })?;
+            replay(entry)?;
+            complete_len += n as u64;
+        }
+
+        file.set_len(complete_len)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Journal { file, sync })
+    }
+
+    /// Appends an entry. It is handed to the OS in a single write, so it survives the process
+    /// dying, and synced to the disk if [`JournalSync::EveryEntry`] is set.
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), Error> {
+        let mut buf = serde_json::to_vec(entry).map_err(Error::WriteJournal)?;
+        buf.push(b'\n');
+        self.file.write_all(&buf)?;
+        if self.sync == JournalSync::EveryEntry {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// An accepted transaction and the changes it made to the account and transaction record.
+    Accepted {
+        sequence: u64,
+        transaction: Box<EngineTransaction>,
+        status: AccountStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        status_reason: Option<StatusReason>,
+        /// The postings to the ledger, which make the changes of the balance.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        postings: Vec<Posting>,
+        record: Option<RecordEntry>,
+    },
+    /// A rejected transaction which opened the (empty) account of a new client.
+    AccountOpened { sequence: u64, client_id: u16 },
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::engine::{AdminOperations, Engine, EngineConfig, LedgerAccount};
+
+    fn engine_state(engine: &Engine) -> Vec<(u16, String, String, bool)> {
+        let mut items: Vec<_> = engine
+            .output_items()
+            .map(|item| {
+                (
+                    item.client,
+                    item.available.to_string(),
+                    item.held.to_string(),
+                    item.locked,
+                )
+            })
+            .collect();
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut engine = Engine::recover(&path).unwrap();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(deposit(2, 2001, "50.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(3, 3001, "10.00"))
+            .unwrap_err();
+        engine.process_transaction(dispute(1, 1001)).unwrap();
+        engine.process_transaction(dispute(2, 2001)).unwrap();
+        engine.process_transaction(chargeback(2, 2001)).unwrap();
+        let expected = engine_state(&engine);
+        let cash = ledger_balance(&engine, LedgerAccount::Cash);
+        drop(engine);
+
+        let mut engine = Engine::recover(&path).unwrap();
+        assert_eq!(engine_state(&engine), expected);
+        assert_eq!(engine.transaction_count(), 6);
+        // The ledger is rebuilt from the journaled postings
+        assert_eq!(ledger_balance(&engine, LedgerAccount::Cash), cash);
+        engine.trial_balance().unwrap();
+
+        // Dispute statuses and transaction IDs are restored
+        engine.process_transaction(resolve(1, 1001)).unwrap();
+        let result = engine.process_transaction(deposit(3, 1001, "1.00"));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1001))
+        ));
+        let expected = engine_state(&engine);
+        drop(engine);
+
+        // Changes made after a recovery are appended to the same journal. The last rejected
+        // transaction did not change anything, so it is not recorded.
+        let engine = Engine::recover(&path).unwrap();
+        assert_eq!(engine_state(&engine), expected);
+        assert_eq!(engine.transaction_count(), 7);
+    }
+
+    #[test]
+    fn test_recover_admin_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let config = || EngineConfig {
+            admin_operations: AdminOperations::Allowed,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::recover_with_config(config(), &path).unwrap();
+        engine.process_transaction(deposit(1, 1, "10.00")).unwrap();
+        engine.process_transaction(chargeback(1, 9)).unwrap();
+        engine.process_transaction(unlock(1, 1)).unwrap();
+        engine.process_transaction(freeze(1, 2)).unwrap();
+        drop(engine);
+
+        // The account flags and the admin actions are replayed
+        let engine = Engine::recover_with_config(config(), &path).unwrap();
+        let account1 = engine.account(1).unwrap();
+        assert_eq!(account1.status(), AccountStatus::Frozen);
+        assert_eq!(
+            account1.status_reason(),
+            Some(&StatusReason::Operator("under review".to_string()))
+        );
+        assert_eq!(engine.admin_actions().len(), 2);
+        assert_eq!(engine.admin_actions()[0].reason, "reviewed");
+    }
+
+    #[test]
+    fn test_recover_after_ledger_overflow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut engine = Engine::recover(&path).unwrap();
+        let max = Decimal::MAX.to_string();
+        engine.process_transaction(deposit(1, 1, &max)).unwrap();
+        // The cash account overflows, but client 2 can hold the deposit
+        engine.process_transaction(deposit(2, 2, "1")).unwrap();
+        assert_eq!(get_client_output(&engine, 2).total, decimal("1"));
+        // Client 1 cannot
+        let result = engine.process_transaction(deposit(1, 3, "1"));
+        assert!(matches!(result, Err(Error::DecimalOverflow)));
+        let expected = engine_state(&engine);
+        drop(engine);
+
+        let engine = Engine::recover(&path).unwrap();
+        assert_eq!(engine_state(&engine), expected);
+        // The ledger no longer adds up
+        assert!(matches!(
+            engine.trial_balance(),
+            Err(Error::DecimalOverflow)
+        ));
+    }
+
+    #[test]
+    fn test_recover_discards_incomplete_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        let mut engine = Engine::recover(&path).unwrap();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        drop(engine);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"entry":"accepted","sequ"#).unwrap();
+        drop(file);
+
+        let mut engine = Engine::recover_with_config(EngineConfig::default(), &path).unwrap();
+        engine
+            .process_transaction(deposit(1, 1002, "1.00"))
+            .unwrap();
+        drop(engine);
+
+        let engine = Engine::recover(&path).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("101.00"));
+        assert_eq!(engine.transaction_count(), 2);
+    }
+
+    #[test]
+    fn test_recover_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let config = || EngineConfig {
+            journal_sync: JournalSync::EveryEntry,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::recover_with_config(config(), &path).unwrap();
+        engine.process_transaction(deposit(1, 1, "10.00")).unwrap();
+        engine.process_transaction(dispute(1, 1)).unwrap();
+        let expected = engine_state(&engine);
+        drop(engine);
+
+        let engine = Engine::recover_with_config(config(), &path).unwrap();
+        assert_eq!(engine_state(&engine), expected);
+    }
+
+    #[test]
+    fn test_recover_rejects_corrupted_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        std::fs::write(&path, "not json\n").unwrap();
+
+        let result = Engine::recover(&path);
+        assert!(matches!(result, Err(Error::ReadJournal { line: 1, .. })));
+    }
+}
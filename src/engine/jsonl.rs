@@ -0,0 +1,157 @@
+use std::io::BufRead;
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::{
+    engine::{
+        csv::{CsvInputRecord, TransactionType},
+        source::TransactionSource,
+    },
+    error::Error,
+};
+
+/// Reads transaction records from JSON Lines input, one object per line with the same fields
+/// as the CSV columns: `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+///
+/// The amount can be a string or a number; numbers are read from their text, without a
+/// floating point conversion. So can the optional `timestamp`, a number being milliseconds since
+/// the epoch. Blank lines are skipped.
+pub struct JsonLinesReader<R: BufRead> {
+    reader: R,
+    buffer: String,
+    line: u64,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    pub fn from_reader(r: R) -> Self {
+        JsonLinesReader {
+            reader: r,
+            buffer: String::new(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> TransactionSource for JsonLinesReader<R> {
+    fn read_next(&mut self) -> Result<Option<CsvInputRecord<'_>>, Error> {
+        loop {
+            self.buffer.clear();
+            if self.reader.read_line(&mut self.buffer)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !self.buffer.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut record = parse_json_record(&self.buffer)?;
+        record.line = self.line;
+        Ok(Some(record))
+    }
+
+    fn line(&self) -> u64 {
+        self.line
+    }
+
+    fn raw_fields(&self) -> Vec<String> {
+        let Ok(serde_json::Value::Object(object)) = serde_json::from_str(&self.buffer) else {
+            return vec![self.buffer.trim().to_string()];
+        };
+        ["type", "client", "tx", "amount", "timestamp", "reason"]
+            .into_iter()
+            .map(|key| match object.get(key) {
+                Some(serde_json::Value::String(value)) => value.clone(),
+                Some(serde_json::Value::Null) | None => String::new(),
+                Some(value) => value.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRecord<'a> {
+    r#type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(borrow)]
+    amount: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    timestamp: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    reason: Option<&'a RawValue>,
+}
+
+/// Parses one JSON transaction object. Also used by the HTTP API.
+pub(crate) fn parse_json_record(json: &str) -> Result<CsvInputRecord<'_>, Error> {
+    let record: JsonRecord = serde_json::from_str(json).map_err(Error::DeserializeJsonRecord)?;
+    Ok(CsvInputRecord {
+        r#type: record.r#type,
+        client: record.client,
+        tx: record.tx,
+        amount: raw_text(record.amount)?,
+        timestamp: raw_text(record.timestamp)?,
+        reason: raw_text(record.reason)?,
+        line: 0,
+    })
+}
+
+/// The text of a string or number value, unescaped for strings.
+fn raw_text(value: Option<&RawValue>) -> Result<Option<&str>, Error> {
+    match value.map(RawValue::get) {
+        Some(raw) if raw.starts_with('"') => Ok(Some(
+            serde_json::from_str(raw).map_err(Error::DeserializeJsonRecord)?,
+        )),
+        raw => Ok(raw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineTransaction, test_utils::*};
+
+    #[test]
+    fn test_json_lines_reader() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+
+{"type": "deposit", "client": 1, "tx": 2, "amount": 2.0001}
+{"type": "dispute", "client": 1, "tx": 1}
+{"type": "unknown", "client": 1, "tx": 3, "amount": 1}
+{"type": "withdrawal", "client": 1, "tx": 4, "amount": null}
+"#;
+        let mut engine = Engine::new();
+        let mut reader = JsonLinesReader::from_reader(input.as_bytes());
+        let mut lines = Vec::new();
+        let mut failed = Vec::new();
+        loop {
+            let record = match reader.read_next() {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(e) => {
+                    assert!(matches!(e, Error::DeserializeJsonRecord(_)));
+                    failed.push((reader.line(), reader.raw_fields()));
+                    continue;
+                }
+            };
+            lines.push(record.line);
+            let result = EngineTransaction::parse_csv_record(&record)
+                .and_then(|tx| engine.process_transaction(tx));
+            if record.line == 6 {
+                assert!(matches!(result, Err(Error::InvalidTransactionAmount(_))));
+            } else {
+                result.unwrap();
+            }
+        }
+
+        assert_eq!(lines, [1, 3, 4, 6]);
+        let raw_fields = ["unknown", "1", "3", "1", "", ""]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(failed, [(5, raw_fields)]);
+        let account = get_client_output(&engine, 1);
+        assert_eq!(account.available, decimal("2.0001"));
+        assert_eq!(account.held, decimal("1.5"));
+    }
+}
@@ -0,0 +1,578 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    engine::{checked_decimal::CheckedDecimal, csv::TransactionType},
+    error::Error,
+};
+
+/// An account of the double-entry ledger underneath the client balances.
+///
+/// The available and held accounts of a client always have the client's
+/// [`AccountBalance`](crate::AccountBalance) as their credit balance. The system accounts are the
+/// other side of every posting.
+///
+/// In CSV and JSON, accounts are written as `cash`, `chargeback_losses`, `suspense`,
+/// `client:<id>:available` and `client:<id>:held`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LedgerAccount {
+    /// The funds deposited by the clients and not withdrawn.
+    Cash,
+    /// Deposits charged back by the partner without a dispute, which the client still has.
+    ChargebackLosses,
+    /// Funds waiting to be settled: disputed withdrawals, withdrawals charged back without a
+    /// dispute, manual adjustments and balances known without their postings.
+    Suspense,
+    /// The available funds of a client.
+    Available(u16),
+    /// The held funds of a client.
+    Held(u16),
+}
+
+impl LedgerAccount {
+    /// The client owning the account, or `None` for a system account.
+    pub fn client(self) -> Option<u16> {
+        match self {
+            LedgerAccount::Available(client) | LedgerAccount::Held(client) => Some(client),
+            LedgerAccount::Cash | LedgerAccount::ChargebackLosses | LedgerAccount::Suspense => None,
+        }
+    }
+
+    /// System accounts first, then the accounts of each client in client ID order.
+    fn sort_key(self) -> (u8, u16, u8) {
+        match self {
+            LedgerAccount::Cash => (0, 0, 0),
+            LedgerAccount::ChargebackLosses => (1, 0, 0),
+            LedgerAccount::Suspense => (2, 0, 0),
+            LedgerAccount::Available(client) => (3, client, 0),
+            LedgerAccount::Held(client) => (3, client, 1),
+        }
+    }
+}
+
+impl Ord for LedgerAccount {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for LedgerAccount {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Cash => f.write_str("cash"),
+            LedgerAccount::ChargebackLosses => f.write_str("chargeback_losses"),
+            LedgerAccount::Suspense => f.write_str("suspense"),
+            LedgerAccount::Available(client) => write!(f, "client:{client}:available"),
+            LedgerAccount::Held(client) => write!(f, "client:{client}:held"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let client_account = || {
+            let rest = value.strip_prefix("client:")?;
+            let (client, kind) = rest.split_once(':')?;
+            let client = client.parse().ok()?;
+            match kind {
+                "available" => Some(LedgerAccount::Available(client)),
+                "held" => Some(LedgerAccount::Held(client)),
+                _ => None,
+            }
+        };
+        match value {
+            "cash" => Ok(LedgerAccount::Cash),
+            "chargeback_losses" => Ok(LedgerAccount::ChargebackLosses),
+            "suspense" => Ok(LedgerAccount::Suspense),
+            _ => client_account().ok_or_else(|| format!("unknown ledger account `{value}`")),
+        }
+    }
+}
+
+impl Serialize for LedgerAccount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LedgerAccount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Moves `amount` from the `debit` account to the `credit` account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posting {
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: CheckedDecimal,
+}
+
+impl Posting {
+    pub(crate) fn new(debit: LedgerAccount, credit: LedgerAccount, amount: CheckedDecimal) -> Self {
+        Posting {
+            debit,
+            credit,
+            amount,
+        }
+    }
+}
+
+/// The postings made by a transaction of one client, collected while it is evaluated.
+pub(crate) struct Postings {
+    client_id: u16,
+    postings: Vec<Posting>,
+}
+
+impl Postings {
+    pub(crate) fn new(client_id: u16) -> Self {
+        Postings {
+            client_id,
+            postings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    /// The available account of the client.
+    pub(crate) fn available(&self) -> LedgerAccount {
+        LedgerAccount::Available(self.client_id)
+    }
+
+    /// The held account of the client.
+    pub(crate) fn held(&self) -> LedgerAccount {
+        LedgerAccount::Held(self.client_id)
+    }
+
+    pub(crate) fn extend(&mut self, postings: &[Posting]) {
+        self.postings.extend_from_slice(postings);
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<Posting> {
+        self.postings
+    }
+}
+
+/// The postings bringing a client's available and held funds from the suspense account, for
+/// balances known without their postings: the accounts of snapshots.
+pub(crate) fn suspense_postings(
+    client_id: u16,
+    available: CheckedDecimal,
+    held: CheckedDecimal,
+) -> Result<Vec<Posting>, Error> {
+    let mut postings = Vec::new();
+    for (account, amount) in [
+        (LedgerAccount::Available(client_id), available),
+        (LedgerAccount::Held(client_id), held),
+    ] {
+        if amount.is_sign_negative() {
+            let amount = CheckedDecimal::ZERO.checked_sub(amount)?;
+            postings.push(Posting::new(account, LedgerAccount::Suspense, amount));
+        } else if amount != CheckedDecimal::ZERO {
+            postings.push(Posting::new(LedgerAccount::Suspense, account, amount));
+        }
+    }
+    Ok(postings)
+}
+
+/// The sums of the debits and credits posted to a ledger account.
+///
+/// The sums of a system account stop at the largest decimal instead of overflowing, see
+/// [`Ledger`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerBalance {
+    pub debit: CheckedDecimal,
+    pub credit: CheckedDecimal,
+}
+
+impl LedgerBalance {
+    /// Adds a posting of `amount` to the debit or credit sum of `account`.
+    fn add(
+        &mut self,
+        account: LedgerAccount,
+        side: Side,
+        amount: CheckedDecimal,
+    ) -> Result<(), Error> {
+        let sum = match side {
+            Side::Debit => &mut self.debit,
+            Side::Credit => &mut self.credit,
+        };
+        // The system accounts are shared by all clients, so their sums must not reject a
+        // transaction a client's balance can hold
+        *sum = match account.client() {
+            Some(_) => sum.checked_add(amount)?,
+            None => sum.saturating_add(amount),
+        };
+        Ok(())
+    }
+
+    /// Whether the debit or credit sum of a system account stopped at the largest decimal.
+    fn saturated(&self) -> bool {
+        self.debit == CheckedDecimal::MAX || self.credit == CheckedDecimal::MAX
+    }
+
+    /// The credits less the debits. For a client account, this is its part of the balance.
+    pub fn credit_balance(&self) -> Result<CheckedDecimal, Error> {
+        self.credit.checked_sub(self.debit)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Debit,
+    Credit,
+}
+
+/// A posting made by an accepted transaction, as kept by a [`Ledger`].
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    /// The position of the transaction among those processed by the engine, from 0. With
+    /// several worker threads, among those processed by the worker of the client.
+    pub sequence: u64,
+    pub client: u16,
+    /// The ID of the transaction, as returned by
+    /// [`EngineTransaction::transaction_id`](crate::EngineTransaction::transaction_id).
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: CheckedDecimal,
+}
+
+/// Whether a [`Ledger`] keeps every posting, or only the balances of its accounts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LedgerEntries {
+    /// Only the balances are kept.
+    #[default]
+    Discarded,
+    /// Every posting is kept, see [`Ledger::entries`]. Uses memory for each accepted
+    /// transaction.
+    Kept,
+}
+
+/// The double-entry ledger of an [`Engine`](crate::Engine).
+///
+/// Every deposit, withdrawal, dispute, resolve, chargeback and adjustment posts balanced entries
+/// between the accounts of the client and the system accounts:
+///
+/// | Transaction                            | Debit             | Credit                   |
+/// |----------------------------------------|-------------------|--------------------------|
+/// | deposit                                | cash              | available                |
+/// | withdrawal                             | available         | cash                     |
+/// | dispute of a deposit                   | available         | held                     |
+/// | resolve of a deposit                   | held              | available                |
+/// | chargeback of a deposit                | held              | cash                     |
+/// | chargeback of an undisputed deposit    | chargeback losses | cash                     |
+/// | dispute of a withdrawal                | suspense          | held                     |
+/// | resolve of a withdrawal                | held              | suspense                 |
+/// | chargeback of a withdrawal             | held, then cash   | available, then suspense |
+/// | chargeback of an undisputed withdrawal | cash              | suspense                 |
+/// | credit adjustment                      | suspense          | available                |
+/// | debit adjustment                       | available         | suspense                 |
+///
+/// A chargeback of an unknown transaction has no amount, so it posts nothing.
+///
+/// A posting that would overflow the balance of a client account rejects the transaction. The
+/// debit and credit sums of the system accounts, to which every client posts, stop at the
+/// largest decimal instead: they never reject a transaction, but the
+/// [trial balance](Ledger::trial_balance) then fails.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    balances: BTreeMap<LedgerAccount, LedgerBalance>,
+    entries: Option<Vec<LedgerEntry>>,
+}
+
+impl Ledger {
+    pub(crate) fn new(entries: LedgerEntries) -> Self {
+        Ledger {
+            balances: BTreeMap::new(),
+            entries: match entries {
+                LedgerEntries::Discarded => None,
+                LedgerEntries::Kept => Some(Vec::new()),
+            },
+        }
+    }
+
+    /// The balance of an account, zero if nothing was posted to it.
+    pub fn balance(&self, account: LedgerAccount) -> LedgerBalance {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Iterates over the accounts posted to, system accounts first and then the accounts of
+    /// each client in client ID order.
+    pub fn balances(&self) -> impl Iterator<Item = (LedgerAccount, &LedgerBalance)> {
+        self.balances
+            .iter()
+            .map(|(account, balance)| (*account, balance))
+    }
+
+    /// The postings made since the engine was created, in order, if
+    /// [`EngineConfig::ledger_entries`](crate::EngineConfig::ledger_entries) keeps them.
+    ///
+    /// Postings made before a snapshot was saved or a journal was replayed are not kept. With
+    /// several worker threads, the postings are only in order for each client.
+    pub fn entries(&self) -> &[LedgerEntry] {
+        self.entries.as_deref().unwrap_or_default()
+    }
+
+    /// Lists the balance of every account in the debit or credit column, and checks that both
+    /// columns have the same total.
+    ///
+    /// Fails with [`Error::DecimalOverflow`] if the sums of a system account overflowed.
+    pub fn trial_balance(&self) -> Result<TrialBalance, Error> {
+        let mut trial_balance = TrialBalance {
+            lines: Vec::new(),
+            total_debit: CheckedDecimal::ZERO,
+            total_credit: CheckedDecimal::ZERO,
+        };
+        for (account, balance) in self.balances() {
+            if account.client().is_none() && balance.saturated() {
+                return Err(Error::DecimalOverflow);
+            }
+            let credit_balance = balance.credit_balance()?;
+            let (debit, credit) = if credit_balance.is_sign_negative() {
+                (
+                    CheckedDecimal::ZERO.checked_sub(credit_balance)?,
+                    CheckedDecimal::ZERO,
+                )
+            } else {
+                (CheckedDecimal::ZERO, credit_balance)
+            };
+            trial_balance.total_debit = trial_balance.total_debit.checked_add(debit)?;
+            trial_balance.total_credit = trial_balance.total_credit.checked_add(credit)?;
+            trial_balance.lines.push(TrialBalanceLine {
+                account,
+                debit,
+                credit,
+            });
+        }
+        if trial_balance.total_debit != trial_balance.total_credit {
+            return Err(Error::LedgerOutOfBalance);
+        }
+        Ok(trial_balance)
+    }
+
+    /// The balances of the accounts `postings` are posted to, once they are posted.
+    ///
+    /// This fails if the balance of a client account would overflow. Computing the balances
+    /// before posting them lets a transaction be rejected before any of its changes is applied.
+    pub(crate) fn balances_after(
+        &self,
+        postings: &[Posting],
+    ) -> Result<BTreeMap<LedgerAccount, LedgerBalance>, Error> {
+        let mut balances = BTreeMap::new();
+        for posting in postings {
+            balances
+                .entry(posting.debit)
+                .or_insert_with(|| self.balance(posting.debit))
+                .add(posting.debit, Side::Debit, posting.amount)?;
+            balances
+                .entry(posting.credit)
+                .or_insert_with(|| self.balance(posting.credit))
+                .add(posting.credit, Side::Credit, posting.amount)?;
+        }
+        Ok(balances)
+    }
+
+    /// Posts the postings of an accepted transaction, with the `balances` computed by
+    /// [`Ledger::balances_after`].
+    pub(crate) fn post(
+        &mut self,
+        sequence: u64,
+        client: u16,
+        tx: u32,
+        r#type: TransactionType,
+        postings: &[Posting],
+        balances: BTreeMap<LedgerAccount, LedgerBalance>,
+    ) {
+        self.balances.extend(balances);
+        if let Some(entries) = &mut self.entries {
+            entries.extend(postings.iter().map(|posting| LedgerEntry {
+                sequence,
+                client,
+                tx,
+                r#type,
+                debit: posting.debit,
+                credit: posting.credit,
+                amount: posting.amount,
+            }));
+        }
+    }
+
+    /// Posts to the balances only, for postings restored from a snapshot.
+    pub(crate) fn post_balance(&mut self, posting: &Posting) -> Result<(), Error> {
+        self.balances.entry(posting.debit).or_default().add(
+            posting.debit,
+            Side::Debit,
+            posting.amount,
+        )?;
+        self.balances.entry(posting.credit).or_default().add(
+            posting.credit,
+            Side::Credit,
+            posting.amount,
+        )
+    }
+
+    /// Restores the balance of an account saved in a snapshot.
+    pub(crate) fn restore(&mut self, account: LedgerAccount, balance: LedgerBalance) {
+        self.balances.insert(account, balance);
+    }
+
+    /// Adds the balances and the entries of another ledger to this one.
+    pub(crate) fn merge(&mut self, other: Ledger) -> Result<(), Error> {
+        for (account, balance) in other.balances {
+            let sum = self.balances.entry(account).or_default();
+            sum.add(account, Side::Debit, balance.debit)?;
+            sum.add(account, Side::Credit, balance.credit)?;
+        }
+        if let (Some(entries), Some(other)) = (&mut self.entries, other.entries) {
+            entries.extend(other);
+        }
+        Ok(())
+    }
+}
+
+/// The balance of every ledger account, with the totals of the debit and credit columns, see
+/// [`Engine::trial_balance`](crate::Engine::trial_balance).
+#[derive(Debug, Clone)]
+pub struct TrialBalance {
+    pub lines: Vec<TrialBalanceLine>,
+    pub total_debit: CheckedDecimal,
+    pub total_credit: CheckedDecimal,
+}
+
+/// The balance of a ledger account, in the debit column if more was debited than credited and in
+/// the credit column otherwise.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrialBalanceLine {
+    pub account: LedgerAccount,
+    pub debit: CheckedDecimal,
+    pub credit: CheckedDecimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+
+    #[test]
+    fn test_ledger_account_format() {
+        for (account, text) in [
+            (LedgerAccount::Cash, "cash"),
+            (LedgerAccount::ChargebackLosses, "chargeback_losses"),
+            (LedgerAccount::Suspense, "suspense"),
+            (LedgerAccount::Available(7), "client:7:available"),
+            (LedgerAccount::Held(7), "client:7:held"),
+        ] {
+            assert_eq!(account.to_string(), text);
+            assert_eq!(text.parse::<LedgerAccount>(), Ok(account));
+        }
+        assert!("client:7:pending".parse::<LedgerAccount>().is_err());
+        assert!("client:x:held".parse::<LedgerAccount>().is_err());
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let mut ledger = Ledger::new(LedgerEntries::Kept);
+        let postings = [
+            Posting::new(
+                LedgerAccount::Cash,
+                LedgerAccount::Available(2),
+                decimal("10"),
+            ),
+            Posting::new(
+                LedgerAccount::Available(2),
+                LedgerAccount::Held(2),
+                decimal("4"),
+            ),
+            Posting::new(
+                LedgerAccount::Cash,
+                LedgerAccount::Available(1),
+                decimal("1"),
+            ),
+        ];
+        let balances = ledger.balances_after(&postings).unwrap();
+        ledger.post(0, 2, 1, TransactionType::Deposit, &postings, balances);
+        assert_eq!(ledger.entries().len(), 3);
+
+        let trial_balance = ledger.trial_balance().unwrap();
+        let lines: Vec<_> = trial_balance
+            .lines
+            .iter()
+            .map(|line| (line.account, line.debit, line.credit))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (LedgerAccount::Cash, decimal("11"), CheckedDecimal::ZERO),
+                (
+                    LedgerAccount::Available(1),
+                    CheckedDecimal::ZERO,
+                    decimal("1")
+                ),
+                (
+                    LedgerAccount::Available(2),
+                    CheckedDecimal::ZERO,
+                    decimal("6")
+                ),
+                (LedgerAccount::Held(2), CheckedDecimal::ZERO, decimal("4")),
+            ]
+        );
+        assert_eq!(trial_balance.total_debit, decimal("11"));
+        assert_eq!(trial_balance.total_credit, decimal("11"));
+
+        // A balance restored on one side only throws the ledger out of balance
+        ledger.restore(
+            LedgerAccount::Suspense,
+            LedgerBalance {
+                debit: decimal("1"),
+                credit: CheckedDecimal::ZERO,
+            },
+        );
+        assert!(matches!(
+            ledger.trial_balance(),
+            Err(Error::LedgerOutOfBalance)
+        ));
+    }
+
+    #[test]
+    fn test_suspense_postings() {
+        let postings = suspense_postings(3, decimal("-2"), decimal("5")).unwrap();
+        assert_eq!(
+            postings,
+            [
+                Posting::new(
+                    LedgerAccount::Available(3),
+                    LedgerAccount::Suspense,
+                    decimal("2")
+                ),
+                Posting::new(
+                    LedgerAccount::Suspense,
+                    LedgerAccount::Held(3),
+                    decimal("5")
+                ),
+            ]
+        );
+        assert!(
+            suspense_postings(3, CheckedDecimal::ZERO, CheckedDecimal::ZERO)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
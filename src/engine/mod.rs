@@ -1,150 +1,1263 @@
 mod account;
+mod audit;
 mod checked_decimal;
+mod config;
 mod csv;
+mod history;
+mod journal;
+mod jsonl;
+mod ledger;
+mod output;
+mod policy;
+mod record_store;
+mod registry;
+mod reject;
+mod sharded;
+mod snapshot;
+mod source;
+mod status;
+mod store;
 #[cfg(test)]
 mod test_utils;
+mod timestamp;
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::Path;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-pub use crate::engine::csv::CsvReader;
-use crate::engine::csv::{CsvInputRecord, TransactionType};
-use crate::engine::{account::Account, checked_decimal::CheckedDecimal};
+pub use crate::engine::account::{Account, AccountBalance};
+pub use crate::engine::audit::{AuditEvent, AuditSink, FileAuditSink, MemoryAuditSink, Transition};
+pub use crate::engine::checked_decimal::CheckedDecimal;
+pub use crate::engine::config::{
+    AccountHistory, AccountStorage, AdminOperations, ClientTransactions, DisputeWindow,
+    EngineConfig, JournalSync, OutOfOrderTimestamps, WithdrawalDisputes,
+};
+pub(crate) use crate::engine::csv::COLUMNS;
+pub use crate::engine::csv::{CsvInputRecord, CsvReader, TransactionType};
+pub use crate::engine::history::{HistoryEntry, StatementFormat, write_statement};
+use crate::engine::journal::{Journal, JournalEntry};
+pub use crate::engine::jsonl::JsonLinesReader;
+pub(crate) use crate::engine::jsonl::parse_json_record;
+use crate::engine::ledger::Postings;
+pub use crate::engine::ledger::{
+    Ledger, LedgerAccount, LedgerBalance, LedgerEntries, LedgerEntry, Posting, TrialBalance,
+    TrialBalanceLine,
+};
+pub use crate::engine::output::{OutputFormat, OutputOrder, write_output_items};
+pub use crate::engine::policy::{
+    AllowNegativeAvailablePolicy, DefaultDisputePolicy, DisputeContext, DisputePolicy,
+    SingleDisputePolicy,
+};
+pub use crate::engine::record_store::{DiskRecordStore, MemoryRecordStore, RecordStore};
+pub use crate::engine::registry::{DisputeStatus, TransactionKind, TransactionRecord};
+use crate::engine::registry::{RecordEntry, TransactionRegistry};
+pub use crate::engine::reject::RejectWriter;
+pub use crate::engine::sharded::{Rejection, ShardedEngine};
+pub use crate::engine::source::TransactionSource;
+pub use crate::engine::status::{AccountStatus, StatusReason};
+use crate::engine::store::{AccountStore, new_store};
+pub use crate::engine::timestamp::Timestamp;
 use crate::error::Error;
 
+/// The payment engine, holding the state of every client account seen so far.
 pub struct Engine {
-    account_map: HashMap<u16, Account>,
+    config: EngineConfig,
+    accounts: Box<dyn AccountStore>,
+    registry: TransactionRegistry,
+    transaction_count: u64,
+    /// Added to the transaction count to get the position of a transaction in the dispute
+    /// window, so that windows carry over a snapshot.
+    window_offset: u64,
+    /// The latest timestamp of the transactions accepted so far.
+    latest_timestamp: Option<Timestamp>,
+    /// The admin operations accepted, in order.
+    admin_actions: Vec<AdminAction>,
+    ledger: Ledger,
+    /// The accepted transactions of every client, if the configuration keeps them.
+    history: Option<BTreeMap<u16, Vec<HistoryEntry>>>,
+    journal: Option<Journal>,
+    /// Set when a journaled change could not be applied or audited: the state or the audit sink
+    /// no longer matches the journal, so no further transaction is processed.
+    failed: bool,
+    audit: Option<Box<dyn AuditSink>>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
         Engine {
-            account_map: HashMap::new(),
+            accounts: new_store(config.account_storage),
+            ledger: Ledger::new(config.ledger_entries),
+            history: match config.account_history {
+                AccountHistory::Discarded => None,
+                AccountHistory::Kept => Some(BTreeMap::new()),
+            },
+            registry: TransactionRegistry::new(config.client_transactions),
+            config,
+            audit: None,
+            transaction_count: 0,
+            window_offset: 0,
+            latest_timestamp: None,
+            admin_actions: Vec::new(),
+            journal: None,
+            failed: false,
         }
     }
 
-    pub fn process_transaction(
+    /// Rebuilds the engine from the journal at `journal_path`, with the default configuration.
+    ///
+    /// See [`Engine::recover_with_config`].
+    pub fn recover(journal_path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::recover_with_config(EngineConfig::default(), journal_path)
+    }
+
+    /// Rebuilds the engine from the journal at `journal_path`, creating an empty journal if it
+    /// does not exist. Every change made by the returned engine is appended to the journal
+    /// before it is applied, so the engine can be recovered again if the process dies.
+    ///
+    /// `config` should be the configuration the journal was written with, since it applies to
+    /// the transactions processed after the recovery.
+    pub fn recover_with_config(
+        config: EngineConfig,
+        journal_path: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let mut engine = Engine::with_config(config);
+        engine.attach_journal(journal_path)?;
+        Ok(engine)
+    }
+
+    /// Moves the transaction records into `store`, which keeps them from now on.
+    ///
+    /// The engine starts with a [`MemoryRecordStore`]; a [`DiskRecordStore`] bounds the memory
+    /// used by the records of a large input. Switching stores after a snapshot was loaded keeps
+    /// the loaded records.
+    pub fn set_record_store(&mut self, store: Box<dyn RecordStore>) -> Result<(), Error> {
+        self.registry.set_store(store)
+    }
+
+    /// Replays the journal at `journal_path` onto the current state, creating an empty journal
+    /// if it does not exist, and records every further change in it.
+    ///
+    /// If an audit sink is set, the replayed transactions after its
+    /// [last sequence](AuditSink::last_sequence) are audited.
+    ///
+    /// This is how a run that started from a [snapshot](Engine::load_snapshot) is recovered:
+    /// load the same snapshot, then attach the journal written by the interrupted run.
+    pub fn attach_journal(&mut self, journal_path: impl AsRef<Path>) -> Result<(), Error> {
+        let journal = Journal::open(journal_path.as_ref(), self.config.journal_sync, |entry| {
+            self.replay(entry)
+        })?;
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Reports the state transitions of every transaction accepted from now on to `sink`.
+    ///
+    /// Set it before [attaching a journal](Engine::attach_journal), so that the transactions
+    /// replayed from the journal that are missing from `sink` are reported.
+    pub fn set_audit_sink(&mut self, sink: Box<dyn AuditSink>) {
+        self.audit = Some(sink);
+    }
+
+    /// Applies a transaction to the client's account, creating the account if needed.
+    ///
+    /// A rejected transaction leaves the engine state unchanged, except that the (empty) account
+    /// of a new client is still created.
+    ///
+    /// If a change was recorded in the journal but could not be applied, e.g. because the record
+    /// store failed to write, every further transaction fails with [`Error::EngineFailed`]: the
+    /// engine has to be recovered from the journal. The same goes for an applied transaction
+    /// whose audit event could not be recorded, which fails with [`Error::WriteAudit`].
+    pub fn process_transaction(&mut self, transaction: EngineTransaction) -> Result<(), Error> {
+        self.process_claimed(&transaction, false)
+    }
+
+    /// Applies a transaction like [`Engine::process_transaction`]. If `claimed_elsewhere` is set,
+    /// the transaction ID is claimed by a client held by another engine: the transaction is
+    /// rejected like it would be if this engine held that client's transaction.
+    pub(crate) fn process_claimed(
         &mut self,
-        EngineTransaction { client_id, op }: EngineTransaction,
+        transaction: &EngineTransaction,
+        claimed_elsewhere: bool,
     ) -> Result<(), Error> {
-        let account = self
-            .account_map
-            .entry(client_id)
-            .or_insert_with(Account::new);
+        if self.failed {
+            return Err(Error::EngineFailed);
+        }
+        self.expire_records(self.transaction_count)?;
+        let outcome = match self.evaluate(transaction, claimed_elsewhere) {
+            Ok(outcome) => outcome,
+            Err(err) => return self.reject(transaction.client_id, err),
+        };
+        let sequence = self.transaction_count;
+        let timestamp = transaction.timestamp;
 
-        if account.locked() {
-            return Err(Error::AccountLocked(client_id));
+        // The event is built before the outcome is committed, and recorded once it is applied
+        let event = self
+            .audit
+            .is_some()
+            .then(|| self.audit_event(sequence, timestamp, &outcome));
+
+        if let Some(journal) = &mut self.journal {
+            journal.append(&JournalEntry::Accepted {
+                sequence,
+                status: outcome.account.status(),
+                status_reason: outcome.account.status_reason().cloned(),
+                postings: outcome.postings.clone(),
+                record: outcome
+                    .record
+                    .clone()
+                    .map(|(transaction_id, record)| RecordEntry {
+                        transaction_id,
+                        record,
+                    }),
+                transaction: Box::new(transaction.clone()),
+            })?;
         }
+        // Only counted once journaled, so that the count matches the journal if appending fails
+        self.transaction_count += 1;
 
-        match op {
-            Op::Deposit(deposit) => account.deposit(deposit)?,
-            Op::Withdrawal(withdrawal) => account.withdraw(withdrawal)?,
-            Op::Dispute(dispute) => account.start_dispute(dispute)?,
-            Op::Resolve(resolve) => account.resolve_dispute(resolve)?,
-            Op::Chargeback(chargeback) => account.chargeback(chargeback)?,
+        if let Err(err) = self.commit(sequence, timestamp, outcome) {
+            self.failed = self.journal.is_some();
+            return Err(err);
         }
+        self.record_audit(event)
+    }
 
-        // If the account is locked after processing the transaction, we clear the deposit records to preserve memory
-        if account.locked() {
-            account.clear_deposit_records();
+    /// The audit event of a transaction, built from the account before `outcome` is committed.
+    fn audit_event(
+        &self,
+        sequence: u64,
+        timestamp: Option<Timestamp>,
+        outcome: &Outcome,
+    ) -> AuditEvent {
+        let (before, status_before) = self
+            .accounts
+            .get(outcome.client_id)
+            .map(|account| (account.balance().clone(), account.status()))
+            .unwrap_or_else(|| (AccountBalance::new(), AccountStatus::Active));
+        let status = outcome.account.status();
+        AuditEvent {
+            sequence,
+            client: outcome.client_id,
+            tx: outcome.transaction_id,
+            r#type: outcome.transaction_type,
+            timestamp,
+            before,
+            after: outcome.account.balance().clone(),
+            dispute_status: outcome
+                .previous_dispute_status
+                .zip(outcome.record.as_ref())
+                .map(|(from, (_, record))| Transition {
+                    from,
+                    to: record.dispute_status,
+                }),
+            status: (status != status_before).then_some(Transition {
+                from: status_before,
+                to: status,
+            }),
+            status_reason: outcome.account.status_reason().cloned(),
         }
+    }
 
+    /// Records the event of an applied transaction. If it fails, the engine stops: the event
+    /// would otherwise be missing from the sink, and is only recorded again by a recovery.
+    fn record_audit(&mut self, event: Option<AuditEvent>) -> Result<(), Error> {
+        if let Some(audit) = &mut self.audit
+            && let Some(event) = event
+            && let Err(err) = audit.record(&event)
+        {
+            self.failed = true;
+            return Err(Error::WriteAudit(Box::new(err)));
+        }
         Ok(())
     }
 
-    pub fn output_items(&self) -> impl Iterator<Item = EngineOutputItem> {
-        self.account_map.iter().map(|(&client_id, account)| {
+    /// The number of transactions submitted to [`Engine::process_transaction`], accepted or not.
+    ///
+    /// After a recovery, this counts the transactions up to the last one recorded in the journal.
+    /// Transactions rejected after it are not recorded, and should be submitted again.
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    /// Computes the changes made by a transaction, without applying them.
+    fn evaluate(
+        &self,
+        EngineTransaction {
+            client_id,
+            op,
+            timestamp,
+        }: &EngineTransaction,
+        claimed_elsewhere: bool,
+    ) -> Result<Outcome, Error> {
+        let client_id = *client_id;
+        let check_claim = |transaction_id| {
+            if claimed_elsewhere {
+                return Err(Error::TransactionOwnedByAnotherClient(transaction_id));
+            }
+            Ok(())
+        };
+        let mut account = self
+            .accounts
+            .get(client_id)
+            .cloned()
+            .unwrap_or_else(Account::new);
+        let mut postings = Postings::new(client_id);
+        let mut previous_dispute_status = None;
+
+        let admin_action = AdminAction::from_op(client_id, op, *timestamp);
+        if admin_action.is_some() && self.config.admin_operations == AdminOperations::Rejected {
+            return Err(Error::AdminOperationsDisabled);
+        }
+        if !account.status().permits(op.transaction_type()) {
+            return Err(account.status().rejection(client_id));
+        }
+        if self.config.out_of_order_timestamps == OutOfOrderTimestamps::Rejected
+            && timestamp.is_some()
+            && *timestamp < account.latest_timestamp()
+        {
+            return Err(Error::TimestampOutOfOrder(client_id));
+        }
+        account.observe_timestamp(*timestamp);
+        let window_start = self.window_start(self.transaction_count, *timestamp);
+
+        // Records are kept after the account is locked: their IDs must stay unique across all clients.
+        let mut new_record = false;
+        let record = match op {
+            Op::Deposit(deposit) => {
+                check_claim(deposit.transaction_id)?;
+                self.registry
+                    .check_unused(client_id, deposit.transaction_id)?;
+                account.deposit(deposit, &mut postings)?;
+                new_record = true;
+                Some((
+                    deposit.transaction_id,
+                    TransactionRecord::new(client_id, TransactionKind::Deposit, deposit.amount),
+                ))
+            }
+            Op::Withdrawal(withdrawal) => {
+                check_claim(withdrawal.transaction_id)?;
+                self.registry
+                    .check_unused(client_id, withdrawal.transaction_id)?;
+                account.withdraw(withdrawal, &mut postings)?;
+                new_record = true;
+                Some((
+                    withdrawal.transaction_id,
+                    TransactionRecord::new(
+                        client_id,
+                        TransactionKind::Withdrawal,
+                        withdrawal.amount,
+                    ),
+                ))
+            }
+            Op::Dispute(dispute) => {
+                let transaction_id = dispute.original_transaction_id;
+                check_claim(transaction_id)?;
+                let mut record = self.find_record(client_id, transaction_id)?;
+                self.check_window(transaction_id, &record, window_start)?;
+                previous_dispute_status = Some(record.dispute_status);
+                account.start_dispute(dispute, &mut record, &self.config, &mut postings)?;
+                Some((transaction_id, record))
+            }
+            Op::Resolve(resolve) => {
+                let transaction_id = resolve.original_transaction_id;
+                check_claim(transaction_id)?;
+                let mut record = self.find_record(client_id, transaction_id)?;
+                previous_dispute_status = Some(record.dispute_status);
+                account.resolve_dispute(resolve, &mut record, &self.config, &mut postings)?;
+                Some((transaction_id, record))
+            }
+            Op::Chargeback(chargeback) => {
+                let transaction_id = chargeback.original_transaction_id;
+                check_claim(transaction_id)?;
+                let mut record = self.registry.find(client_id, transaction_id)?;
+                if let Some(record) = &record {
+                    self.check_window(transaction_id, record, window_start)?;
+                    previous_dispute_status = Some(record.dispute_status);
+                }
+                account.chargeback(chargeback, record.as_mut(), &self.config, &mut postings)?;
+                record.map(|record| (transaction_id, record))
+            }
+            Op::Unlock(_) => {
+                account.unlock();
+                None
+            }
+            Op::Freeze(operation) => {
+                account.freeze(&operation.reason);
+                None
+            }
+            Op::Close(operation) => {
+                account.close(client_id, &operation.reason)?;
+                None
+            }
+            Op::Adjustment(adjustment) => {
+                account.adjust(adjustment, &mut postings)?;
+                None
+            }
+        };
+
+        // The client's ledger accounts are checked for overflow before anything is applied
+        let postings = postings.into_vec();
+        let ledger_balances = self.ledger.balances_after(&postings)?;
+
+        Ok(Outcome {
+            client_id,
+            account,
+            record,
+            new_record,
+            admin_action,
+            transaction_type: op.transaction_type(),
+            transaction_id: op.transaction_id(),
+            amount: op.amount(),
+            postings,
+            ledger_balances,
+            previous_dispute_status,
+        })
+    }
+
+    fn find_record(&self, client_id: u16, transaction_id: u32) -> Result<TransactionRecord, Error> {
+        self.registry
+            .find(client_id, transaction_id)?
+            .ok_or(Error::TransactionNotFound(transaction_id))
+    }
+
+    /// Rejects a dispute or chargeback against a transaction whose dispute window started
+    /// before `window_start`, unless a dispute opened in time is in progress.
+    fn check_window(
+        &self,
+        transaction_id: u32,
+        record: &TransactionRecord,
+        window_start: Option<u64>,
+    ) -> Result<(), Error> {
+        if record.dispute_status != DisputeStatus::InProgress
+            && window_start.is_some_and(|start| self.registry.window_closed(transaction_id, start))
+        {
+            return Err(Error::DisputeWindowExpired(transaction_id));
+        }
+        Ok(())
+    }
+
+    fn commit(
+        &mut self,
+        sequence: u64,
+        timestamp: Option<Timestamp>,
+        outcome: Outcome,
+    ) -> Result<(), Error> {
+        let Outcome {
+            client_id,
+            account,
+            record,
+            new_record,
+            admin_action,
+            transaction_type,
+            transaction_id,
+            amount,
+            postings,
+            ledger_balances,
+            previous_dispute_status: _,
+        } = outcome;
+        // The record store is the only part that can fail to write, so it is written first
+        if let Some((transaction_id, record)) = record {
+            self.registry.insert(transaction_id, record)?;
+            if new_record && let Some(position) = self.window_position(sequence, timestamp) {
+                self.registry.open_window(position, transaction_id);
+            }
+        }
+        self.ledger.post(
+            sequence,
+            client_id,
+            transaction_id,
+            transaction_type,
+            &postings,
+            ledger_balances,
+        );
+        if let Some(history) = &mut self.history {
             let balance = account.balance();
-            EngineOutputItem {
+            history.entry(client_id).or_default().push(HistoryEntry {
+                sequence,
                 client: client_id,
+                tx: transaction_id,
+                r#type: transaction_type,
+                // Disputes, resolves and chargebacks without an amount post the amount they apply to
+                amount: amount.or(postings.first().map(|posting| posting.amount)),
                 available: balance.available,
                 held: balance.held,
                 total: balance.computed_total,
-                locked: account.locked(),
+                status: account.status(),
+                timestamp,
+            });
+        }
+        self.accounts.insert(client_id, account);
+        self.admin_actions.extend(admin_action);
+        self.latest_timestamp = self.latest_timestamp.max(timestamp);
+        // A window lasting a duration moves with the accepted timestamps
+        if let Some(DisputeWindow::Duration(_)) = self.config.dispute_window
+            && let Some(start) = self.window_start(sequence, None)
+        {
+            self.registry.expire(start)?;
+        }
+        Ok(())
+    }
+
+    /// The position in the dispute window of the transaction with the given sequence number
+    /// and timestamp: its sequence number for a window counted in transactions, or its time in
+    /// milliseconds for a window lasting a duration.
+    fn window_position(&self, sequence: u64, timestamp: Option<Timestamp>) -> Option<u64> {
+        match self.config.dispute_window? {
+            DisputeWindow::Transactions(_) => Some(self.window_offset + sequence),
+            DisputeWindow::Duration(_) => timestamp
+                .or(self.latest_timestamp)
+                .map(|timestamp| u64::try_from(timestamp.as_millis()).unwrap_or(0)),
+        }
+    }
+
+    /// The earliest window position that can still be disputed by the transaction with the
+    /// given sequence number and timestamp.
+    fn window_start(&self, sequence: u64, timestamp: Option<Timestamp>) -> Option<u64> {
+        let length = match self.config.dispute_window? {
+            DisputeWindow::Transactions(transactions) => transactions,
+            DisputeWindow::Duration(duration) => {
+                u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
             }
-        })
+        };
+        let position = self.window_position(sequence, timestamp)?;
+        Some(position.saturating_sub(length))
+    }
+
+    /// Evicts the records whose dispute window, counted in transactions, closed before the
+    /// transaction with the given sequence number.
+    fn expire_records(&mut self, sequence: u64) -> Result<(), Error> {
+        if let Some(DisputeWindow::Transactions(_)) = self.config.dispute_window
+            && let Some(start) = self.window_start(sequence, None)
+        {
+            self.registry.expire(start)?;
+        }
+        Ok(())
+    }
+
+    /// Counts a transaction rejected with `err`, opening an empty account for a new client, and
+    /// returns `err`.
+    fn reject(&mut self, client_id: u16, err: Error) -> Result<(), Error> {
+        if !self.accounts.contains(client_id) {
+            if let Some(journal) = &mut self.journal {
+                journal.append(&JournalEntry::AccountOpened {
+                    sequence: self.transaction_count,
+                    client_id,
+                })?;
+            }
+            self.accounts.insert(client_id, Account::new());
+        }
+        self.transaction_count += 1;
+        Err(err)
+    }
+
+    /// Moves the accounts and records of an engine holding a disjoint set of clients into this one.
+    fn merge(&mut self, other: Engine) -> Result<(), Error> {
+        for (client_id, account) in other.accounts.iter() {
+            self.accounts.insert(client_id, account.clone());
+        }
+        self.registry.extend(other.registry)?;
+        self.admin_actions.extend(other.admin_actions);
+        self.ledger.merge(other.ledger)?;
+        if let (Some(history), Some(other)) = (&mut self.history, other.history) {
+            history.extend(other);
+        }
+        self.transaction_count += other.transaction_count;
+        Ok(())
+    }
+
+    fn replay(&mut self, entry: JournalEntry) -> Result<(), Error> {
+        match entry {
+            JournalEntry::Accepted {
+                sequence,
+                transaction,
+                status,
+                status_reason,
+                postings,
+                record,
+            } => {
+                self.expire_records(sequence)?;
+                let client_id = transaction.client_id;
+                let mut account = self
+                    .accounts
+                    .get(client_id)
+                    .cloned()
+                    .unwrap_or_else(Account::new);
+                account.apply_journaled(client_id, &postings, status, status_reason)?;
+                account.observe_timestamp(transaction.timestamp);
+                let ledger_balances = self.ledger.balances_after(&postings)?;
+                // Transactions applied but missing from the audit sink are audited now
+                let audited = self
+                    .audit
+                    .as_ref()
+                    .is_some_and(|audit| audit.last_sequence().is_none_or(|last| sequence > last));
+                let previous_dispute_status = match (&transaction.op, &record) {
+                    (Op::Dispute(_) | Op::Resolve(_) | Op::Chargeback(_), Some(entry))
+                        if audited =>
+                    {
+                        self.registry
+                            .find(client_id, entry.transaction_id)?
+                            .map(|record| record.dispute_status)
+                    }
+                    _ => None,
+                };
+                let outcome = Outcome {
+                    client_id,
+                    account,
+                    record: record.map(|entry| (entry.transaction_id, entry.record)),
+                    new_record: matches!(transaction.op, Op::Deposit(_) | Op::Withdrawal(_)),
+                    admin_action: AdminAction::from_op(
+                        client_id,
+                        &transaction.op,
+                        transaction.timestamp,
+                    ),
+                    transaction_type: transaction.transaction_type(),
+                    transaction_id: transaction.transaction_id(),
+                    amount: transaction.amount(),
+                    postings,
+                    ledger_balances,
+                    previous_dispute_status,
+                };
+                let event =
+                    audited.then(|| self.audit_event(sequence, transaction.timestamp, &outcome));
+                self.commit(sequence, transaction.timestamp, outcome)?;
+                self.transaction_count = sequence + 1;
+                self.record_audit(event)?;
+            }
+            JournalEntry::AccountOpened {
+                sequence,
+                client_id,
+            } => {
+                self.expire_records(sequence)?;
+                if !self.accounts.contains(client_id) {
+                    self.accounts.insert(client_id, Account::new());
+                }
+                self.transaction_count = sequence + 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the account of the given client, if any transaction has been seen for it.
+    pub fn account(&self, client_id: u16) -> Option<&Account> {
+        self.accounts.get(client_id)
+    }
+
+    /// Iterates over all accounts in client ID order.
+    pub fn accounts(&self) -> impl Iterator<Item = (u16, &Account)> {
+        self.accounts.iter()
+    }
+
+    /// Iterates over the summary of every account in client ID order.
+    pub fn output_items(&self) -> impl Iterator<Item = EngineOutputItem> {
+        self.accounts()
+            .map(|(client_id, account)| EngineOutputItem::new(client_id, account))
+    }
+
+    /// Returns the summary of every account in the given order. Accounts that compare equal
+    /// are in client ID order.
+    pub fn sorted_output_items(&self, order: OutputOrder) -> Vec<EngineOutputItem> {
+        let mut items: Vec<_> = self.output_items().collect();
+        // The sorts are stable, so the client ID order is kept for equal keys
+        match order {
+            OutputOrder::Client => {}
+            OutputOrder::Total => items.sort_by_key(|item| Reverse(item.total)),
+            OutputOrder::Locked => items.sort_by_key(|item| !item.locked),
+        }
+        items
+    }
+
+    /// The summary of one account, or `None` if the client has no account.
+    pub fn output_item(&self, client_id: u16) -> Option<EngineOutputItem> {
+        self.account(client_id)
+            .map(|account| EngineOutputItem::new(client_id, account))
+    }
+
+    /// The admin operations accepted so far, in the order they were applied.
+    ///
+    /// With several worker threads, the operations are only in order for each client.
+    pub fn admin_actions(&self) -> &[AdminAction] {
+        &self.admin_actions
+    }
+
+    /// The accepted transactions of a client in order, each with the balance and status it left.
+    ///
+    /// Empty unless [`EngineConfig::account_history`] keeps the history.
+    pub fn history(&self, client_id: u16) -> &[HistoryEntry] {
+        self.history
+            .as_ref()
+            .and_then(|history| history.get(&client_id))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The double-entry ledger backing the account balances.
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Computes the [trial balance](Ledger::trial_balance) of the ledger, and checks that the
+    /// client accounts of the ledger match the account balances.
+    pub fn trial_balance(&self) -> Result<TrialBalance, Error> {
+        let trial_balance = self.ledger.trial_balance()?;
+        let mut clients: Vec<_> = self.accounts().map(|(client_id, _)| client_id).collect();
+        clients.extend(
+            self.ledger
+                .balances()
+                .filter_map(|(account, _)| account.client()),
+        );
+        clients.sort_unstable();
+        clients.dedup();
+        for client_id in clients {
+            let balance = self
+                .account(client_id)
+                .map(|account| account.balance().clone())
+                .unwrap_or_else(AccountBalance::new);
+            let available = self.ledger.balance(LedgerAccount::Available(client_id));
+            let held = self.ledger.balance(LedgerAccount::Held(client_id));
+            if available.credit_balance()? != balance.available
+                || held.credit_balance()? != balance.held
+            {
+                return Err(Error::LedgerMismatch(client_id));
+            }
+        }
+        Ok(trial_balance)
+    }
+
+    /// Returns the deposits and withdrawals accepted for a client, in no particular order.
+    ///
+    /// Unless [`ClientTransactions::Indexed`] is configured, this visits every record, which
+    /// reads the whole file of a [`DiskRecordStore`].
+    pub fn client_transactions(&self, client_id: u16) -> Result<Vec<TransactionSummary>, Error> {
+        let mut transactions = Vec::new();
+        self.registry
+            .for_each_of_client(client_id, &mut |transaction_id, record| {
+                transactions.push(TransactionSummary {
+                    tx: transaction_id,
+                    kind: record.kind,
+                    amount: record.amount,
+                    dispute_status: record.dispute_status,
+                    disputed: record.disputed,
+                    charged_back: record.charged_back,
+                });
+                Ok(())
+            })?;
+        Ok(transactions)
     }
 }
 
-#[derive(Debug)]
+/// The changes made by an accepted transaction, before they are applied.
+struct Outcome {
+    client_id: u16,
+    account: Account,
+    record: Option<(u32, TransactionRecord)>,
+    /// Whether `record` is a newly accepted deposit or withdrawal.
+    new_record: bool,
+    /// The record of an admin operation.
+    admin_action: Option<AdminAction>,
+    transaction_type: TransactionType,
+    /// See [`EngineTransaction::transaction_id`].
+    transaction_id: u32,
+    /// See [`EngineTransaction::amount`].
+    amount: Option<CheckedDecimal>,
+    /// The postings to the ledger, already applied to `account`.
+    postings: Vec<Posting>,
+    /// The balances of the ledger accounts `postings` are posted to, once they are posted.
+    ledger_balances: BTreeMap<LedgerAccount, LedgerBalance>,
+    /// The dispute status `record` had before a dispute, resolve or chargeback.
+    previous_dispute_status: Option<DisputeStatus>,
+}
+
+/// A validated transaction for a single client, ready to be processed by the [`Engine`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EngineTransaction {
     client_id: u16,
     op: Op,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
 }
 
-#[derive(Debug)]
-enum Op {
+/// The operation carried by an [`EngineTransaction`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Op {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
     Dispute(Dispute),
     Resolve(Resolve),
     Chargeback(Chargeback),
+    Unlock(AdminOperation),
+    Freeze(AdminOperation),
+    Close(AdminOperation),
+    Adjustment(Adjustment),
 }
 
-#[derive(Debug)]
-struct Deposit {
+/// Credits `amount` to the client's available funds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deposit {
     transaction_id: u32,
     amount: CheckedDecimal,
 }
 
-#[derive(Debug)]
-struct Withdrawal {
+/// Debits `amount` from the client's available funds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    transaction_id: u32,
     amount: CheckedDecimal,
 }
 
-#[derive(Debug)]
-struct Dispute {
+/// Opens a dispute against a previous deposit or withdrawal, or a part of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dispute {
     original_transaction_id: u32,
+    /// The part of the transaction disputed, or all of it not disputed yet when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<CheckedDecimal>,
 }
 
-#[derive(Debug)]
-struct Resolve {
+/// Closes a dispute, or a part of it, and releases the held funds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resolve {
     original_transaction_id: u32,
+    /// The disputed part resolved, or all of it when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<CheckedDecimal>,
 }
 
-#[derive(Debug)]
-struct Chargeback {
+/// Closes a dispute, or a part of it, by reversing the disputed amount and locking the account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chargeback {
     original_transaction_id: u32,
+    /// The disputed part charged back, or all of it when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<CheckedDecimal>,
 }
 
-impl EngineTransaction {
-    pub fn parse_csv_record(record: &CsvInputRecord) -> Result<Self, Error> {
-        let parse_amount = || -> Result<CheckedDecimal, Error> {
-            let amount = record.amount.map(CheckedDecimal::parse).transpose()?;
-            let amount = amount.ok_or(Error::InvalidTransactionAmount("Amount is required"))?;
-            if amount.is_sign_negative() {
-                return Err(Error::InvalidTransactionAmount("Amount cannot be negative"));
+impl Op {
+    pub fn transaction_type(&self) -> TransactionType {
+        match self {
+            Op::Deposit(_) => TransactionType::Deposit,
+            Op::Withdrawal(_) => TransactionType::Withdrawal,
+            Op::Dispute(_) => TransactionType::Dispute,
+            Op::Resolve(_) => TransactionType::Resolve,
+            Op::Chargeback(_) => TransactionType::Chargeback,
+            Op::Unlock(_) => TransactionType::Unlock,
+            Op::Freeze(_) => TransactionType::Freeze,
+            Op::Close(_) => TransactionType::Close,
+            Op::Adjustment(_) => TransactionType::Adjustment,
+        }
+    }
+
+    /// See [`EngineTransaction::amount`].
+    pub fn amount(&self) -> Option<CheckedDecimal> {
+        match self {
+            Op::Deposit(deposit) => Some(deposit.amount),
+            Op::Withdrawal(withdrawal) => Some(withdrawal.amount),
+            Op::Dispute(dispute) => dispute.amount,
+            Op::Resolve(resolve) => resolve.amount,
+            Op::Chargeback(chargeback) => chargeback.amount,
+            Op::Adjustment(adjustment) => Some(adjustment.amount),
+            Op::Unlock(_) | Op::Freeze(_) | Op::Close(_) => None,
+        }
+    }
+
+    /// See [`EngineTransaction::transaction_id`].
+    pub fn transaction_id(&self) -> u32 {
+        match self {
+            Op::Deposit(deposit) => deposit.transaction_id,
+            Op::Withdrawal(withdrawal) => withdrawal.transaction_id,
+            Op::Dispute(dispute) => dispute.original_transaction_id,
+            Op::Resolve(resolve) => resolve.original_transaction_id,
+            Op::Chargeback(chargeback) => chargeback.original_transaction_id,
+            Op::Unlock(operation) | Op::Freeze(operation) | Op::Close(operation) => {
+                operation.action_id
             }
-            Ok(amount)
-        };
+            Op::Adjustment(adjustment) => adjustment.action_id,
+        }
+    }
+}
+
+/// An admin operation without an amount: an unlock, a freeze or a close.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminOperation {
+    action_id: u32,
+    reason: String,
+}
+
+/// Credits the client's available funds by `amount`, or debits them if it is negative. An admin
+/// operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Adjustment {
+    action_id: u32,
+    amount: CheckedDecimal,
+    reason: String,
+}
+
+impl Deposit {
+    pub fn transaction_id(&self) -> u32 {
+        self.transaction_id
+    }
+
+    pub fn amount(&self) -> CheckedDecimal {
+        self.amount
+    }
+}
+
+impl Withdrawal {
+    pub fn transaction_id(&self) -> u32 {
+        self.transaction_id
+    }
+
+    pub fn amount(&self) -> CheckedDecimal {
+        self.amount
+    }
+}
+
+impl Dispute {
+    pub fn original_transaction_id(&self) -> u32 {
+        self.original_transaction_id
+    }
+
+    pub fn amount(&self) -> Option<CheckedDecimal> {
+        self.amount
+    }
+}
+
+impl Resolve {
+    pub fn original_transaction_id(&self) -> u32 {
+        self.original_transaction_id
+    }
+
+    pub fn amount(&self) -> Option<CheckedDecimal> {
+        self.amount
+    }
+}
+
+impl Chargeback {
+    pub fn original_transaction_id(&self) -> u32 {
+        self.original_transaction_id
+    }
+
+    pub fn amount(&self) -> Option<CheckedDecimal> {
+        self.amount
+    }
+}
+
+impl AdminOperation {
+    fn new(action_id: u32, reason: &str) -> Result<Self, Error> {
+        Ok(AdminOperation {
+            action_id,
+            reason: validate_reason(reason)?,
+        })
+    }
+
+    /// The ID of the admin action, from the `tx` column.
+    pub fn action_id(&self) -> u32 {
+        self.action_id
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl Adjustment {
+    /// The ID of the admin action, from the `tx` column.
+    pub fn action_id(&self) -> u32 {
+        self.action_id
+    }
+
+    pub fn amount(&self) -> CheckedDecimal {
+        self.amount
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
 
-        let op = match record.r#type {
-            TransactionType::Deposit => Op::Deposit(Deposit {
-                transaction_id: record.tx,
-                amount: parse_amount()?,
+impl EngineTransaction {
+    /// Creates a deposit. Fails if `amount` is negative.
+    pub fn deposit(
+        client_id: u16,
+        transaction_id: u32,
+        amount: CheckedDecimal,
+    ) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Deposit(Deposit {
+                transaction_id,
+                amount: validate_amount(amount)?,
+            }),
+            timestamp: None,
+        })
+    }
+
+    /// Creates a withdrawal. Fails if `amount` is negative.
+    pub fn withdrawal(
+        client_id: u16,
+        transaction_id: u32,
+        amount: CheckedDecimal,
+    ) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Withdrawal(Withdrawal {
+                transaction_id,
+                amount: validate_amount(amount)?,
             }),
-            TransactionType::Withdrawal => Op::Withdrawal(Withdrawal {
-                amount: parse_amount()?,
+            timestamp: None,
+        })
+    }
+
+    /// Creates a dispute against the transaction `original_transaction_id`.
+    pub fn dispute(client_id: u16, original_transaction_id: u32) -> Self {
+        EngineTransaction {
+            client_id,
+            op: Op::Dispute(Dispute {
+                original_transaction_id,
+                amount: None,
+            }),
+            timestamp: None,
+        }
+    }
+
+    /// Creates a dispute against `amount` of the transaction `original_transaction_id`. Fails if
+    /// `amount` is not positive.
+    pub fn partial_dispute(
+        client_id: u16,
+        original_transaction_id: u32,
+        amount: CheckedDecimal,
+    ) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Dispute(Dispute {
+                original_transaction_id,
+                amount: Some(validate_partial_amount(amount)?),
             }),
-            TransactionType::Dispute => Op::Dispute(Dispute {
-                original_transaction_id: record.tx,
+            timestamp: None,
+        })
+    }
+
+    /// Creates a resolve for the disputed transaction `original_transaction_id`.
+    pub fn resolve(client_id: u16, original_transaction_id: u32) -> Self {
+        EngineTransaction {
+            client_id,
+            op: Op::Resolve(Resolve {
+                original_transaction_id,
+                amount: None,
             }),
-            TransactionType::Resolve => Op::Resolve(Resolve {
-                original_transaction_id: record.tx,
+            timestamp: None,
+        }
+    }
+
+    /// Creates a resolve for `amount` of the disputed transaction `original_transaction_id`.
+    /// Fails if `amount` is not positive.
+    pub fn partial_resolve(
+        client_id: u16,
+        original_transaction_id: u32,
+        amount: CheckedDecimal,
+    ) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Resolve(Resolve {
+                original_transaction_id,
+                amount: Some(validate_partial_amount(amount)?),
             }),
-            TransactionType::Chargeback => Op::Chargeback(Chargeback {
-                original_transaction_id: record.tx,
+            timestamp: None,
+        })
+    }
+
+    /// Creates a chargeback for the disputed transaction `original_transaction_id`.
+    pub fn chargeback(client_id: u16, original_transaction_id: u32) -> Self {
+        EngineTransaction {
+            client_id,
+            op: Op::Chargeback(Chargeback {
+                original_transaction_id,
+                amount: None,
             }),
-        };
+            timestamp: None,
+        }
+    }
 
+    /// Creates a chargeback for `amount` of the disputed transaction `original_transaction_id`.
+    /// Fails if `amount` is not positive.
+    pub fn partial_chargeback(
+        client_id: u16,
+        original_transaction_id: u32,
+        amount: CheckedDecimal,
+    ) -> Result<Self, Error> {
         Ok(EngineTransaction {
-            client_id: record.client,
-            op,
+            client_id,
+            op: Op::Chargeback(Chargeback {
+                original_transaction_id,
+                amount: Some(validate_partial_amount(amount)?),
+            }),
+            timestamp: None,
         })
     }
+
+    /// Creates an unlock of the client's account, lifting a lock or a freeze. Fails if `reason`
+    /// is blank.
+    pub fn unlock(client_id: u16, action_id: u32, reason: &str) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Unlock(AdminOperation::new(action_id, reason)?),
+            timestamp: None,
+        })
+    }
+
+    /// Creates a freeze of the client's account. Fails if `reason` is blank.
+    pub fn freeze(client_id: u16, action_id: u32, reason: &str) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Freeze(AdminOperation::new(action_id, reason)?),
+            timestamp: None,
+        })
+    }
+
+    /// Creates a close of the client's account. Fails if `reason` is blank.
+    pub fn close(client_id: u16, action_id: u32, reason: &str) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Close(AdminOperation::new(action_id, reason)?),
+            timestamp: None,
+        })
+    }
+
+    /// Creates a manual adjustment of the client's available funds by the signed `amount`.
+    /// Fails if `reason` is blank.
+    pub fn adjustment(
+        client_id: u16,
+        action_id: u32,
+        amount: CheckedDecimal,
+        reason: &str,
+    ) -> Result<Self, Error> {
+        Ok(EngineTransaction {
+            client_id,
+            op: Op::Adjustment(Adjustment {
+                action_id,
+                amount,
+                reason: validate_reason(reason)?,
+            }),
+            timestamp: None,
+        })
+    }
+
+    /// Validates a CSV record and converts it into a transaction.
+    pub fn parse_csv_record(record: &CsvInputRecord) -> Result<Self, Error> {
+        let optional_amount = || record.amount.map(CheckedDecimal::parse).transpose();
+        let parse_amount = || -> Result<CheckedDecimal, Error> {
+            optional_amount()?.ok_or(Error::InvalidTransactionAmount("Amount is required"))
+        };
+        let reason = record.reason.unwrap_or_default();
+
+        let mut transaction = match record.r#type {
+            TransactionType::Deposit => Self::deposit(record.client, record.tx, parse_amount()?)?,
+            TransactionType::Withdrawal => {
+                Self::withdrawal(record.client, record.tx, parse_amount()?)?
+            }
+            // An amount makes disputes, resolves and chargebacks partial
+            TransactionType::Dispute => match optional_amount()? {
+                Some(amount) => Self::partial_dispute(record.client, record.tx, amount)?,
+                None => Self::dispute(record.client, record.tx),
+            },
+            TransactionType::Resolve => match optional_amount()? {
+                Some(amount) => Self::partial_resolve(record.client, record.tx, amount)?,
+                None => Self::resolve(record.client, record.tx),
+            },
+            TransactionType::Chargeback => match optional_amount()? {
+                Some(amount) => Self::partial_chargeback(record.client, record.tx, amount)?,
+                None => Self::chargeback(record.client, record.tx),
+            },
+            TransactionType::Unlock => Self::unlock(record.client, record.tx, reason)?,
+            TransactionType::Freeze => Self::freeze(record.client, record.tx, reason)?,
+            TransactionType::Close => Self::close(record.client, record.tx, reason)?,
+            TransactionType::Adjustment => {
+                Self::adjustment(record.client, record.tx, parse_amount()?, reason)?
+            }
+        };
+        transaction.timestamp = record.timestamp.map(Timestamp::parse).transpose()?;
+        Ok(transaction)
+    }
+
+    /// Sets the time of the transaction.
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    pub fn op(&self) -> &Op {
+        &self.op
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        self.op.transaction_type()
+    }
+
+    /// The transaction ID, the ID of the referenced transaction for disputes, resolves and
+    /// chargebacks, or the action ID for admin operations.
+    pub fn transaction_id(&self) -> u32 {
+        self.op.transaction_id()
+    }
+
+    /// The reason given for an admin operation.
+    pub fn reason(&self) -> Option<&str> {
+        match &self.op {
+            Op::Unlock(operation) | Op::Freeze(operation) | Op::Close(operation) => {
+                Some(&operation.reason)
+            }
+            Op::Adjustment(adjustment) => Some(&adjustment.reason),
+            _ => None,
+        }
+    }
+
+    /// The amount, or the partial amount of disputes, resolves and chargebacks.
+    pub fn amount(&self) -> Option<CheckedDecimal> {
+        self.op.amount()
+    }
 }
 
+fn validate_amount(amount: CheckedDecimal) -> Result<CheckedDecimal, Error> {
+    if amount.is_sign_negative() {
+        return Err(Error::InvalidTransactionAmount("Amount cannot be negative"));
+    }
+    Ok(amount)
+}
+
+fn validate_reason(reason: &str) -> Result<String, Error> {
+    if reason.trim().is_empty() {
+        return Err(Error::ReasonRequired);
+    }
+    Ok(reason.to_string())
+}
+
+fn validate_partial_amount(amount: CheckedDecimal) -> Result<CheckedDecimal, Error> {
+    if amount <= CheckedDecimal::ZERO {
+        return Err(Error::InvalidTransactionAmount("Amount must be positive"));
+    }
+    Ok(amount)
+}
+
+/// The summary of one account, as written to the output CSV.
 #[derive(Debug, Serialize)]
 pub struct EngineOutputItem {
     pub client: u16,
@@ -152,10 +1265,89 @@ pub struct EngineOutputItem {
     pub held: CheckedDecimal,
     pub total: CheckedDecimal,
     pub locked: bool,
+    pub status: AccountStatus,
+    /// Why the account is not active.
+    pub reason: Option<String>,
+}
+
+impl EngineOutputItem {
+    fn new(client_id: u16, account: &Account) -> Self {
+        let balance = account.balance();
+        EngineOutputItem {
+            client: client_id,
+            available: balance.available,
+            held: balance.held,
+            total: balance.computed_total,
+            locked: account.locked(),
+            status: account.status(),
+            reason: account.status_reason().map(ToString::to_string),
+        }
+    }
+}
+
+/// A deposit or withdrawal accepted by the engine, with where it is in the dispute lifecycle.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionSummary {
+    pub tx: u32,
+    pub kind: TransactionKind,
+    pub amount: CheckedDecimal,
+    pub dispute_status: DisputeStatus,
+    /// The part of the amount under dispute.
+    pub disputed: CheckedDecimal,
+    /// The part of the amount charged back.
+    pub charged_back: CheckedDecimal,
+}
+
+/// An admin operation accepted by the engine, recorded with its reason.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminAction {
+    pub action_id: u32,
+    pub client: u16,
+    pub r#type: TransactionType,
+    /// The amount of an adjustment.
+    pub amount: Option<CheckedDecimal>,
+    pub reason: String,
+    pub timestamp: Option<Timestamp>,
+}
+
+impl AdminAction {
+    /// The record of `op` if it is an admin operation.
+    fn from_op(client: u16, op: &Op, timestamp: Option<Timestamp>) -> Option<Self> {
+        let (r#type, operation, amount) = match op {
+            Op::Unlock(operation) => (TransactionType::Unlock, operation, None),
+            Op::Freeze(operation) => (TransactionType::Freeze, operation, None),
+            Op::Close(operation) => (TransactionType::Close, operation, None),
+            Op::Adjustment(adjustment) => {
+                return Some(AdminAction {
+                    action_id: adjustment.action_id,
+                    client,
+                    r#type: TransactionType::Adjustment,
+                    amount: Some(adjustment.amount),
+                    reason: adjustment.reason.clone(),
+                    timestamp,
+                });
+            }
+            Op::Deposit(_)
+            | Op::Withdrawal(_)
+            | Op::Dispute(_)
+            | Op::Resolve(_)
+            | Op::Chargeback(_) => return None,
+        };
+        Some(AdminAction {
+            action_id: operation.action_id,
+            client,
+            r#type,
+            amount,
+            reason: operation.reason.clone(),
+            timestamp,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
     use crate::engine::test_utils::*;
 
@@ -165,7 +1357,7 @@ mod tests {
             deposit(1, 1001, "100.00"),
             deposit(1, 1002, "50.00"),
             deposit(2, 2001, "25.00"),
-            withdrawal(1, "80.00"),
+            withdrawal(1, 1003, "80.00"),
         ];
 
         let mut engine = Engine::new();
@@ -197,7 +1389,7 @@ mod tests {
             .process_transaction(deposit(1, 1001, "100.00"))
             .unwrap();
 
-        let result = engine.process_transaction(withdrawal(1, "150.00"));
+        let result = engine.process_transaction(withdrawal(1, 1002, "150.00"));
         if let Err(Error::InsufficientFunds) = result {
             // Expected error
         } else {
@@ -264,7 +1456,9 @@ mod tests {
         engine
             .process_transaction(deposit(1, 1001, "100.00"))
             .unwrap();
-        engine.process_transaction(withdrawal(1, "50.00")).unwrap();
+        engine
+            .process_transaction(withdrawal(1, 1002, "50.00"))
+            .unwrap();
 
         // Insufficient funds for dispute
         engine.process_transaction(dispute(1, 1001)).unwrap_err();
@@ -274,6 +1468,690 @@ mod tests {
         assert_eq!(account1.held, decimal("0.00"));
     }
 
+    #[test]
+    fn test_transaction_id_unique_across_clients() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(1, 1002, "10.00"))
+            .unwrap();
+
+        let result = engine.process_transaction(deposit(1, 1001, "5.00"));
+        assert!(matches!(result, Err(Error::DuplicateTransactionId(1001))));
+        let result = engine.process_transaction(deposit(1, 1002, "5.00"));
+        assert!(matches!(result, Err(Error::DuplicateTransactionId(1002))));
+        let result = engine.process_transaction(deposit(2, 1001, "5.00"));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1001))
+        ));
+        let result = engine.process_transaction(withdrawal(2, 1002, "0.00"));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1002))
+        ));
+
+        // A rejected transaction does not claim its ID
+        engine
+            .process_transaction(withdrawal(2, 2001, "1.00"))
+            .unwrap_err();
+        engine
+            .process_transaction(deposit(2, 2001, "1.00"))
+            .unwrap();
+
+        let account2 = get_client_output(&engine, 2);
+        assert_eq!(account2.available, decimal("1.00"));
+    }
+
+    #[test]
+    fn test_dispute_transaction_of_another_client() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(deposit(2, 2001, "100.00"))
+            .unwrap();
+
+        let result = engine.process_transaction(dispute(2, 1001));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1001))
+        ));
+        let result = engine.process_transaction(resolve(2, 1001));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1001))
+        ));
+
+        // Unlike a chargeback for an unknown transaction, this does not lock the account
+        let result = engine.process_transaction(chargeback(2, 1001));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(1001))
+        ));
+        assert!(!get_client_output(&engine, 2).locked);
+
+        let result = engine.process_transaction(dispute(2, 9999));
+        assert!(matches!(result, Err(Error::TransactionNotFound(9999))));
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("100.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_rejected_by_default() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(1, 1002, "40.00"))
+            .unwrap();
+
+        let result = engine.process_transaction(dispute(1, 1002));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1002))));
+        let result = engine.process_transaction(chargeback(1, 1002));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1002))));
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("60.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+        assert!(!account1.locked);
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_resolve_chargeback() {
+        let mut engine = Engine::with_config(EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Allowed,
+            ..Default::default()
+        });
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(1, 1002, "40.00"))
+            .unwrap();
+
+        // dispute 1002, the withdrawn funds are provisionally credited to held
+        {
+            engine.process_transaction(dispute(1, 1002)).unwrap();
+
+            let account1 = get_client_output(&engine, 1);
+            assert_eq!(account1.available, decimal("60.00"));
+            assert_eq!(account1.held, decimal("40.00"));
+            assert_eq!(account1.total, decimal("100.00"));
+        }
+
+        // resolve 1002, the credit is reverted
+        {
+            engine.process_transaction(resolve(1, 1002)).unwrap();
+
+            let account1 = get_client_output(&engine, 1);
+            assert_eq!(account1.available, decimal("60.00"));
+            assert_eq!(account1.held, decimal("0.00"));
+            assert_eq!(account1.total, decimal("60.00"));
+        }
+
+        // dispute and chargeback 1002, the funds are returned to the client
+        {
+            engine.process_transaction(dispute(1, 1002)).unwrap();
+            engine.process_transaction(chargeback(1, 1002)).unwrap();
+
+            let account1 = get_client_output(&engine, 1);
+            assert_eq!(account1.available, decimal("100.00"));
+            assert_eq!(account1.held, decimal("0.00"));
+            assert_eq!(account1.total, decimal("100.00"));
+            assert!(account1.locked);
+        }
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let mut engine = Engine::new();
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+
+        // Partial disputes coexist up to the deposit amount
+        engine
+            .process_transaction(partial_dispute(1, 1, "30.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1, "50.00"))
+            .unwrap();
+        let result = engine.process_transaction(partial_dispute(1, 1, "20.01"));
+        assert!(matches!(result, Err(Error::DisputeAmountExceeded(1))));
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("20.00"));
+        assert_eq!(account1.held, decimal("80.00"));
+
+        // A resolve without an amount releases every disputed part
+        engine
+            .process_transaction(partial_resolve(1, 1, "10.00"))
+            .unwrap();
+        let result = engine.process_transaction(partial_resolve(1, 1, "70.01"));
+        assert!(matches!(result, Err(Error::DisputeAmountExceeded(1))));
+        engine.process_transaction(resolve(1, 1)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("100.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+        let result = engine.process_transaction(resolve(1, 1));
+        assert!(matches!(result, Err(Error::DisputeNotStarted(1))));
+
+        // A dispute without an amount disputes what is left, and a partial chargeback only
+        // reverses its amount
+        engine
+            .process_transaction(partial_dispute(1, 1, "40.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 1)).unwrap();
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeAlreadyStarted(1))));
+        engine
+            .process_transaction(partial_chargeback(1, 1, "30.00"))
+            .unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("0.00"));
+        assert_eq!(account1.held, decimal("70.00"));
+        assert_eq!(account1.total, decimal("70.00"));
+        assert!(account1.locked);
+
+        let summary = &engine.client_transactions(1).unwrap()[0];
+        assert_eq!(summary.dispute_status, DisputeStatus::InProgress);
+        assert_eq!(summary.disputed, decimal("70.00"));
+        assert_eq!(summary.charged_back, decimal("30.00"));
+
+        // Partial amounts are read from the amount column and must be positive
+        let csv_data = "type,client,tx,amount\ndispute,1,1,12.5\nresolve,1,1,0\nchargeback,1,1,\n";
+        let mut reader = CsvReader::from_reader(csv_data.as_bytes()).unwrap();
+        let record = reader.read_next().unwrap().unwrap();
+        let transaction = EngineTransaction::parse_csv_record(&record).unwrap();
+        assert_eq!(transaction.amount(), Some(decimal("12.5")));
+        let record = reader.read_next().unwrap().unwrap();
+        assert!(matches!(
+            EngineTransaction::parse_csv_record(&record),
+            Err(Error::InvalidTransactionAmount(_))
+        ));
+        let record = reader.read_next().unwrap().unwrap();
+        let transaction = EngineTransaction::parse_csv_record(&record).unwrap();
+        assert_eq!(transaction.amount(), None);
+    }
+
+    #[test]
+    fn test_partial_chargeback_of_undisputed_rest() {
+        let mut engine = Engine::with_config(EngineConfig {
+            admin_operations: AdminOperations::Allowed,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1, "30.00"))
+            .unwrap();
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+
+        // Only the disputed part was charged back, the rest can still be disputed
+        let summary = &engine.client_transactions(1).unwrap()[0];
+        assert_eq!(summary.dispute_status, DisputeStatus::NotStarted);
+        assert_eq!(summary.charged_back, decimal("30.00"));
+        engine.process_transaction(unlock(1, 100)).unwrap();
+        engine.process_transaction(dispute(1, 1)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("0.00"));
+        assert_eq!(account1.held, decimal("70.00"));
+
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+        let summary = &engine.client_transactions(1).unwrap()[0];
+        assert_eq!(summary.dispute_status, DisputeStatus::Chargebacked);
+        assert_eq!(summary.charged_back, decimal("100.00"));
+        engine.process_transaction(unlock(1, 101)).unwrap();
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1))));
+        engine.trial_balance().unwrap();
+    }
+
+    #[test]
+    fn test_admin_operations() {
+        let result = Engine::new().process_transaction(unlock(1, 1));
+        assert!(matches!(result, Err(Error::AdminOperationsDisabled)));
+
+        let mut engine = Engine::with_config(EngineConfig {
+            admin_operations: AdminOperations::Allowed,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+        engine.process_transaction(deposit(1, 2, "50.00")).unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1, "40.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_chargeback(1, 1, "30.00"))
+            .unwrap();
+        let result = engine.process_transaction(deposit(1, 3, "1.00"));
+        assert!(matches!(result, Err(Error::AccountLocked(1))));
+        assert_eq!(
+            engine.account(1).unwrap().status_reason(),
+            Some(&StatusReason::Chargeback)
+        );
+
+        // After an unlock, the disputes of the deposits go on
+        engine.process_transaction(unlock(1, 100)).unwrap();
+        engine.process_transaction(resolve(1, 1)).unwrap();
+        engine.process_transaction(dispute(1, 2)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("70.00"));
+        assert_eq!(account1.held, decimal("50.00"));
+        assert!(!account1.locked);
+
+        // A frozen account cannot withdraw, but disputes go on
+        engine.process_transaction(freeze(1, 101)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.status, AccountStatus::Frozen);
+        assert_eq!(account1.reason.as_deref(), Some("under review"));
+        let result = engine.process_transaction(withdrawal(1, 3, "1.00"));
+        assert!(matches!(result, Err(Error::AccountFrozen(1))));
+        engine.process_transaction(resolve(1, 2)).unwrap();
+        engine.process_transaction(unlock(1, 102)).unwrap();
+
+        // Only an empty account can be closed, after which everything is rejected
+        let result = engine.process_transaction(close(1, 103));
+        assert!(matches!(result, Err(Error::AccountNotEmpty(1))));
+        let result = engine.process_transaction(adjustment(1, 104, "-120.01"));
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+        engine
+            .process_transaction(adjustment(1, 105, "-120.00"))
+            .unwrap();
+        engine.process_transaction(close(1, 106)).unwrap();
+        let result = engine.process_transaction(unlock(1, 107));
+        assert!(matches!(result, Err(Error::AccountClosed(1))));
+        let result = engine.process_transaction(deposit(1, 4, "1.00"));
+        assert!(matches!(result, Err(Error::AccountClosed(1))));
+        assert_eq!(engine.account(1).unwrap().status(), AccountStatus::Closed);
+
+        let actions: Vec<_> = engine
+            .admin_actions()
+            .iter()
+            .map(|action| (action.action_id, action.r#type, action.amount))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                (100, TransactionType::Unlock, None),
+                (101, TransactionType::Freeze, None),
+                (102, TransactionType::Unlock, None),
+                (105, TransactionType::Adjustment, Some(decimal("-120.00"))),
+                (106, TransactionType::Close, None),
+            ]
+        );
+        assert_eq!(engine.admin_actions()[1].reason, "under review");
+
+        // Admin operations need a reason
+        let result = EngineTransaction::freeze(1, 108, " ");
+        assert!(matches!(result, Err(Error::ReasonRequired)));
+
+        // The account state and the admin actions carry over a snapshot
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let engine = Engine::load_snapshot(buf.as_slice()).unwrap();
+        assert_eq!(engine.account(1).unwrap().status(), AccountStatus::Closed);
+        assert_eq!(engine.admin_actions().len(), 5);
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut engine = Engine::with_config(EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Allowed,
+            admin_operations: AdminOperations::Allowed,
+            ledger_entries: LedgerEntries::Kept,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+        engine.process_transaction(deposit(1, 6, "30.00")).unwrap();
+        engine
+            .process_transaction(withdrawal(1, 2, "30.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 1)).unwrap();
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+        engine.process_transaction(deposit(2, 3, "50.00")).unwrap();
+        engine
+            .process_transaction(withdrawal(2, 4, "20.00"))
+            .unwrap();
+        engine.process_transaction(dispute(2, 4)).unwrap();
+        engine.process_transaction(resolve(2, 4)).unwrap();
+        engine.process_transaction(chargeback(2, 3)).unwrap();
+        engine.process_transaction(unlock(2, 1)).unwrap();
+        engine
+            .process_transaction(adjustment(2, 2, "5.00"))
+            .unwrap();
+        // Rejected transactions post nothing
+        engine
+            .process_transaction(withdrawal(2, 5, "100.00"))
+            .unwrap_err();
+
+        // The client accounts of the ledger hold the account balances
+        let cash = ledger_balance(&engine, LedgerAccount::Cash);
+        assert_eq!(cash, decimal("20.00"));
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::ChargebackLosses),
+            decimal("-50.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Suspense),
+            decimal("-5.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Available(1)),
+            decimal("0.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Held(1)),
+            decimal("0.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Available(2)),
+            decimal("35.00")
+        );
+        let trial_balance = engine.trial_balance().unwrap();
+        assert_eq!(trial_balance.total_debit, decimal("55.00"));
+        assert_eq!(trial_balance.total_credit, decimal("55.00"));
+
+        // Every posting of the accepted transactions is kept, in order
+        let entries: Vec<_> = engine
+            .ledger()
+            .entries()
+            .iter()
+            .map(|entry| (entry.tx, entry.r#type, entry.debit, entry.credit))
+            .collect();
+        assert_eq!(entries.len(), 11);
+        assert_eq!(
+            entries[4],
+            (
+                1,
+                TransactionType::Chargeback,
+                LedgerAccount::Held(1),
+                LedgerAccount::Cash
+            )
+        );
+        assert_eq!(
+            entries[7],
+            (
+                4,
+                TransactionType::Dispute,
+                LedgerAccount::Suspense,
+                LedgerAccount::Held(2)
+            )
+        );
+        assert_eq!(
+            entries[9],
+            (
+                3,
+                TransactionType::Chargeback,
+                LedgerAccount::ChargebackLosses,
+                LedgerAccount::Cash,
+            )
+        );
+
+        // The ledger balances carry over a snapshot, its entries do not
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let engine = Engine::load_snapshot(buf.as_slice()).unwrap();
+        assert_eq!(ledger_balance(&engine, LedgerAccount::Cash), cash);
+        assert!(engine.ledger().entries().is_empty());
+        engine.trial_balance().unwrap();
+    }
+
+    #[test]
+    fn test_ledger_withdrawal_chargeback() {
+        let mut engine = Engine::with_config(EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Allowed,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+        engine
+            .process_transaction(withdrawal(1, 2, "40.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 2)).unwrap();
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Suspense),
+            decimal("-40.00")
+        );
+
+        // The withdrawn funds come back to the bank and to the client, settling the suspense
+        engine.process_transaction(chargeback(1, 2)).unwrap();
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Suspense),
+            decimal("0.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Cash),
+            decimal("-100.00")
+        );
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::Available(1)),
+            decimal("100.00")
+        );
+        engine.trial_balance().unwrap();
+    }
+
+    #[test]
+    fn test_undisputed_chargeback_after_unlock() {
+        let mut engine = Engine::with_config(EngineConfig {
+            admin_operations: AdminOperations::Allowed,
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "100.00")).unwrap();
+        engine
+            .process_transaction(partial_chargeback(1, 1, "30.00"))
+            .unwrap();
+        engine.process_transaction(unlock(1, 100)).unwrap();
+        let result = engine.process_transaction(partial_chargeback(1, 1, "70.01"));
+        assert!(matches!(result, Err(Error::DisputeAmountExceeded(1))));
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+        engine.process_transaction(unlock(1, 101)).unwrap();
+
+        // The whole deposit was charged back, and its loss is only posted once
+        let result = engine.process_transaction(chargeback(1, 1));
+        assert!(matches!(result, Err(Error::DisputeAlreadyChargedBack(1))));
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1))));
+        assert_eq!(
+            ledger_balance(&engine, LedgerAccount::ChargebackLosses),
+            decimal("-100.00")
+        );
+        let summary = &engine.client_transactions(1).unwrap()[0];
+        assert_eq!(summary.dispute_status, DisputeStatus::Chargebacked);
+        assert_eq!(summary.charged_back, decimal("100.00"));
+        engine.trial_balance().unwrap();
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: Some(DisputeWindow::Transactions(2)),
+            ..Default::default()
+        });
+        engine.process_transaction(deposit(1, 1, "10.00")).unwrap();
+        engine.process_transaction(deposit(1, 2, "20.00")).unwrap();
+        engine.process_transaction(dispute(1, 2)).unwrap();
+        // Rejected transactions count towards the window as well
+        assert!(engine.process_transaction(dispute(1, 9)).is_err());
+
+        // Deposit 1 could only be disputed by the next two transactions
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(1))));
+        let result = engine.process_transaction(chargeback(1, 1));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(1))));
+        // Its ID stays claimed, but its client is forgotten
+        let result = engine.process_transaction(deposit(2, 1, "1.00"));
+        assert!(matches!(result, Err(Error::DuplicateTransactionId(1))));
+        let result = engine.process_transaction(dispute(2, 1));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(1))));
+
+        // The dispute of deposit 2 was opened in time: it can still be resolved, but not
+        // opened again
+        engine.process_transaction(resolve(1, 2)).unwrap();
+        let result = engine.process_transaction(dispute(1, 2));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(2))));
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("30.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+        assert!(!account1.locked);
+        assert!(engine.client_transactions(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_client_transactions_indexed() {
+        let config = |client_transactions| EngineConfig {
+            dispute_window: Some(DisputeWindow::Transactions(3)),
+            client_transactions,
+            ..Default::default()
+        };
+        let mut scanned = Engine::with_config(config(ClientTransactions::Scanned));
+        let mut indexed = Engine::with_config(config(ClientTransactions::Indexed));
+        let summaries = |engine: &Engine, client| {
+            let mut summaries: Vec<_> = engine
+                .client_transactions(client)
+                .unwrap()
+                .into_iter()
+                .map(|summary| (summary.tx, summary.dispute_status, summary.disputed))
+                .collect();
+            summaries.sort_by_key(|summary| summary.0);
+            summaries
+        };
+
+        for transaction in [
+            deposit(1, 1, "10.00"),
+            deposit(2, 2, "20.00"),
+            dispute(1, 1),
+            deposit(1, 3, "5.00"),
+            withdrawal(2, 4, "1.00"),
+            deposit(1, 5, "1.00"),
+        ] {
+            scanned.process_transaction(transaction.clone()).unwrap();
+            indexed.process_transaction(transaction).unwrap();
+            for client in [1, 2, 3] {
+                assert_eq!(summaries(&indexed, client), summaries(&scanned, client));
+            }
+        }
+        // Deposit 2 expired, deposit 1 is kept while its dispute is in progress
+        let transactions: Vec<_> = summaries(&indexed, 1).iter().map(|s| s.0).collect();
+        assert_eq!(transactions, [1, 3, 5]);
+        assert_eq!(summaries(&indexed, 2).len(), 1);
+
+        scanned.process_transaction(resolve(1, 1)).unwrap();
+        indexed.process_transaction(resolve(1, 1)).unwrap();
+        assert_eq!(summaries(&indexed, 1), summaries(&scanned, 1));
+        let transactions: Vec<_> = summaries(&indexed, 1).iter().map(|s| s.0).collect();
+        assert_eq!(transactions, [3, 5]);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let csv_data = r#"type, client, tx, amount, timestamp
+deposit, 1, 1, 10.0, 2024-03-01T10:00:00Z
+deposit, 1, 2, 5.0, 1709287200000
+withdrawal, 1, 3, 1.0, 2024-03-01T09:59:59Z
+withdrawal, 1, 4, 1.0,
+deposit, 2, 5, 1.0, 2024-02-01T00:00:00+01:00
+deposit, 2, 6, 1.0, yesterday
+"#;
+        let mut engine = Engine::with_config(EngineConfig {
+            out_of_order_timestamps: OutOfOrderTimestamps::Rejected,
+            ..Default::default()
+        });
+        let mut reader = CsvReader::from_reader(csv_data.as_bytes()).unwrap();
+        let mut errors = Vec::new();
+        while let Some(record) = reader.read_next().unwrap() {
+            let result = EngineTransaction::parse_csv_record(&record)
+                .and_then(|tx| engine.process_transaction(tx));
+            if let Err(e) = result {
+                errors.push((record.line, e));
+            }
+        }
+
+        // Each client has its own order; transactions without a timestamp are not checked
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], (4, Error::TimestampOutOfOrder(1))));
+        assert!(matches!(&errors[1], (7, Error::InvalidTimestamp(value)) if value == "yesterday"));
+        let account1 = engine.account(1).unwrap();
+        assert_eq!(account1.balance().available, decimal("14.0"));
+        assert_eq!(
+            account1.latest_timestamp().unwrap().to_string(),
+            "2024-03-01T10:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_csv_optional_columns() {
+        let csv_data = "type,client,tx,amount,timestamp,reason\nfreeze,1,1,,,review\n";
+        let mut reader = CsvReader::from_reader(csv_data.as_bytes()).unwrap();
+        let record = reader.read_next().unwrap().unwrap();
+        assert_eq!(record.timestamp, None);
+        assert_eq!(record.reason, Some("review"));
+
+        // Records are read by position, so a reason cannot stand in the timestamp column
+        let csv_data = "type,client,tx,amount,reason\nfreeze,1,1,,review\n";
+        let result = CsvReader::from_reader(csv_data.as_bytes());
+        assert!(matches!(result, Err(Error::UnexpectedCsvColumn(name)) if name == "reason"));
+        let csv_data = "type,client,tx,amount,timestamp,note\n";
+        let result = CsvReader::from_reader(csv_data.as_bytes());
+        assert!(matches!(result, Err(Error::UnexpectedCsvColumn(name)) if name == "note"));
+    }
+
+    #[test]
+    fn test_dispute_window_duration() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: Some(DisputeWindow::Duration(Duration::from_secs(60))),
+            ..Default::default()
+        });
+        let at = |tx: EngineTransaction, seconds: i64| {
+            tx.with_timestamp(Timestamp::from_millis(seconds * 1000))
+        };
+        engine
+            .process_transaction(at(deposit(1, 1, "10.00"), 0))
+            .unwrap();
+        engine
+            .process_transaction(at(deposit(1, 2, "10.00"), 30))
+            .unwrap();
+        // Exactly 60 seconds later is still in time
+        engine.process_transaction(at(dispute(1, 1), 60)).unwrap();
+        let result = engine.process_transaction(at(dispute(1, 2), 91));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(2))));
+        // The rejected dispute did not move the clock, but an accepted deposit does: deposit 2
+        // is evicted, while deposit 1 is kept until its dispute is closed
+        engine
+            .process_transaction(at(deposit(1, 3, "1.00"), 100))
+            .unwrap();
+        let result = engine.process_transaction(dispute(1, 2));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(2))));
+        engine.process_transaction(chargeback(1, 1)).unwrap();
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.total, decimal("11.00"));
+        assert!(account1.locked);
+    }
+
+    #[test]
+    fn test_dispute_window_far_future_timestamp() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: Some(DisputeWindow::Duration(Duration::from_secs(60))),
+            ..Default::default()
+        });
+        let at = |tx: EngineTransaction, seconds: i64| {
+            tx.with_timestamp(Timestamp::from_millis(seconds * 1000))
+        };
+        engine
+            .process_transaction(at(deposit(1, 1, "10.00"), 0))
+            .unwrap();
+        // The clock is shared: another client's transaction years ahead evicts deposit 1
+        engine
+            .process_transaction(at(deposit(2, 2, "1.00"), 100_000_000))
+            .unwrap();
+        let result = engine.process_transaction(at(dispute(1, 1), 10));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(1))));
+    }
+
     #[test]
     fn test_example_csv() {
         let csv_data = r#"type, client, tx, amount
@@ -0,0 +1,123 @@
+use std::io::Write;
+
+use crate::{engine::EngineOutputItem, error::Error};
+
+/// How account summaries are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OutputFormat {
+    /// CSV with a `client, available, held, total, locked, status, reason` header.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+    /// A single JSON array of objects.
+    Json,
+}
+
+/// The order of account summaries, see [`Engine::sorted_output_items`].
+///
+/// [`Engine::sorted_output_items`]: crate::Engine::sorted_output_items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OutputOrder {
+    /// By client ID.
+    #[default]
+    Client,
+    /// By total funds, largest first.
+    Total,
+    /// Locked accounts first.
+    Locked,
+}
+
+/// Writes account summaries in the given format.
+pub fn write_output_items<W: Write>(
+    mut w: W,
+    format: OutputFormat,
+    items: impl IntoIterator<Item = EngineOutputItem>,
+) -> Result<(), Error> {
+    match format {
+        OutputFormat::Csv => {
+            let mut w = csv::Writer::from_writer(w);
+            for item in items {
+                w.serialize(item).map_err(Error::WriteCsvRecord)?;
+            }
+            w.flush()?;
+        }
+        OutputFormat::JsonLines => {
+            for item in items {
+                serde_json::to_writer(&mut w, &item).map_err(Error::WriteJsonRecord)?;
+                w.write_all(b"\n")?;
+            }
+            w.flush()?;
+        }
+        OutputFormat::Json => {
+            w.write_all(b"[")?;
+            for (i, item) in items.into_iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut w, &item).map_err(Error::WriteJsonRecord)?;
+            }
+            w.write_all(b"]\n")?;
+            w.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, test_utils::*};
+
+    fn write(format: OutputFormat) -> String {
+        let mut engine = Engine::new();
+        engine.process_transaction(deposit(1, 1, "1.5")).unwrap();
+        engine.process_transaction(chargeback(2, 2)).unwrap();
+        let mut buf = Vec::new();
+        write_output_items(&mut buf, format, engine.output_items()).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_output_order() {
+        let mut engine = Engine::new();
+        for (client, amount) in [(3, "5.0"), (1, "1.0"), (2, "5.0"), (4, "7.0")] {
+            engine
+                .process_transaction(deposit(client, u32::from(client), amount))
+                .unwrap();
+        }
+        engine.process_transaction(chargeback(2, 999)).unwrap();
+
+        let clients = |order| -> Vec<_> {
+            engine
+                .sorted_output_items(order)
+                .into_iter()
+                .map(|item| item.client)
+                .collect()
+        };
+        assert_eq!(clients(OutputOrder::Client), [1, 2, 3, 4]);
+        assert_eq!(clients(OutputOrder::Total), [4, 2, 3, 1]);
+        assert_eq!(clients(OutputOrder::Locked), [2, 1, 3, 4]);
+        let output_items: Vec<_> = engine.output_items().map(|item| item.client).collect();
+        assert_eq!(output_items, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_output_formats() {
+        assert_eq!(
+            write(OutputFormat::Csv),
+            "client,available,held,total,locked,status,reason\n\
+             1,1.5,0,1.5,false,active,\n\
+             2,0,0,0,true,locked,unknown_chargeback\n"
+        );
+        let json1 = r#"{"client":1,"available":"1.5","held":"0","total":"1.5","locked":false,"status":"active","reason":null}"#;
+        let json2 = r#"{"client":2,"available":"0","held":"0","total":"0","locked":true,"status":"locked","reason":"unknown_chargeback"}"#;
+        assert_eq!(
+            write(OutputFormat::JsonLines),
+            format!("{json1}\n{json2}\n")
+        );
+        assert_eq!(write(OutputFormat::Json), format!("[{json1},{json2}]\n"));
+    }
+}
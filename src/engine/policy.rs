@@ -0,0 +1,217 @@
+use std::fmt;
+
+use crate::{
+    engine::{
+        account::AccountBalance,
+        checked_decimal::CheckedDecimal,
+        registry::{DisputeStatus, TransactionKind},
+    },
+    error::Error,
+};
+
+/// Business rules consulted by the engine before opening, resolving or charging back a dispute.
+///
+/// The engine always enforces the rules that keep the account state consistent (e.g. a dispute
+/// cannot be opened twice at the same time, and only a disputed transaction can be resolved).
+/// A policy can only reject more transactions, or decide what an undisputed chargeback does.
+pub trait DisputePolicy: fmt::Debug + Send + Sync {
+    /// Called before opening a dispute on a transaction, or on a part of it that is not disputed
+    /// or charged back yet. Other parts of the transaction may be under dispute already.
+    fn check_dispute(&self, context: &DisputeContext) -> Result<(), Error> {
+        // If the available balance is less than the disputed amount, we cannot start a dispute
+        if context.kind == TransactionKind::Deposit && context.balance.available < context.amount {
+            return Err(Error::InsufficientFunds);
+        }
+        Ok(())
+    }
+
+    /// Called before resolving a dispute that is in progress.
+    fn check_resolve(&self, _context: &DisputeContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called for a chargeback against a transaction that is not disputed, or that cannot be
+    /// found (`context` is `None`). Returning `Ok` locks the account without changing its
+    /// balance, returning an error rejects the chargeback.
+    fn check_undisputed_chargeback(
+        &self,
+        _transaction_id: u32,
+        _context: Option<&DisputeContext>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The transaction targeted by a dispute operation, and the balance of its account.
+#[derive(Debug)]
+pub struct DisputeContext<'a> {
+    pub transaction_id: u32,
+    pub kind: TransactionKind,
+    /// The amount the operation applies to: the amount given with a partial dispute, resolve
+    /// or chargeback, or otherwise the whole undisputed amount for a dispute and the whole
+    /// disputed amount for a resolve or chargeback.
+    pub amount: CheckedDecimal,
+    /// The amount of the transaction itself.
+    pub transaction_amount: CheckedDecimal,
+    /// The part of the transaction under dispute before the operation.
+    pub disputed: CheckedDecimal,
+    /// The part of the transaction charged back before the operation.
+    pub charged_back: CheckedDecimal,
+    /// Whether a dispute of the transaction, or of a part of it, was resolved before.
+    pub resolved: bool,
+    pub status: DisputeStatus,
+    pub balance: &'a AccountBalance,
+}
+
+/// The default rules:
+/// - A dispute is rejected if the available funds are less than the disputed deposit.
+/// - A transaction can be disputed again after its previous dispute was resolved.
+/// - A chargeback without a dispute locks the account.
+#[derive(Debug, Default)]
+pub struct DefaultDisputePolicy;
+
+impl DisputePolicy for DefaultDisputePolicy {}
+
+/// Like [`DefaultDisputePolicy`], but a deposit can be disputed even if its funds have already
+/// been withdrawn, leaving the available balance negative.
+#[derive(Debug, Default)]
+pub struct AllowNegativeAvailablePolicy;
+
+impl DisputePolicy for AllowNegativeAvailablePolicy {
+    fn check_dispute(&self, _context: &DisputeContext) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Like [`DefaultDisputePolicy`], but a transaction can only be disputed once: once a dispute
+/// was resolved, even of a part of the transaction, no part of it can be disputed again.
+#[derive(Debug, Default)]
+pub struct SingleDisputePolicy;
+
+impl DisputePolicy for SingleDisputePolicy {
+    fn check_dispute(&self, context: &DisputeContext) -> Result<(), Error> {
+        if context.resolved {
+            return Err(Error::DisputeNotAllowed(context.transaction_id));
+        }
+        DefaultDisputePolicy.check_dispute(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::engine::{Engine, EngineConfig};
+
+    fn engine_with_policy(dispute_policy: Arc<dyn DisputePolicy>) -> Engine {
+        Engine::with_config(EngineConfig {
+            dispute_policy,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_allow_negative_available() {
+        let mut engine = engine_with_policy(Arc::new(AllowNegativeAvailablePolicy));
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(1, 1002, "80.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 1001)).unwrap();
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("-80.00"));
+        assert_eq!(account1.held, decimal("100.00"));
+        assert_eq!(account1.total, decimal("20.00"));
+    }
+
+    #[test]
+    fn test_single_dispute() {
+        let mut engine = engine_with_policy(Arc::new(SingleDisputePolicy));
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 1001)).unwrap();
+        engine.process_transaction(resolve(1, 1001)).unwrap();
+
+        let result = engine.process_transaction(dispute(1, 1001));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1001))));
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("100.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+
+        // A partially resolved dispute stays in progress, but the resolved part cannot be
+        // disputed again, nor can any other part
+        engine
+            .process_transaction(deposit(1, 1002, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1002, "40.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1002, "30.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_resolve(1, 1002, "40.00"))
+            .unwrap();
+        let result = engine.process_transaction(partial_dispute(1, 1002, "40.00"));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1002))));
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.held, decimal("30.00"));
+
+        // The default policy lets the resolved part be disputed again
+        let mut engine = engine_with_policy(Arc::new(DefaultDisputePolicy));
+        engine
+            .process_transaction(deposit(1, 1002, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1002, "40.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1002, "30.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_resolve(1, 1002, "40.00"))
+            .unwrap();
+        engine
+            .process_transaction(partial_dispute(1, 1002, "40.00"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_custom_undisputed_chargeback() {
+        #[derive(Debug)]
+        struct RejectUndisputedChargeback;
+
+        impl DisputePolicy for RejectUndisputedChargeback {
+            fn check_undisputed_chargeback(
+                &self,
+                transaction_id: u32,
+                _context: Option<&DisputeContext>,
+            ) -> Result<(), Error> {
+                Err(Error::DisputeNotStarted(transaction_id))
+            }
+        }
+
+        let mut engine = engine_with_policy(Arc::new(RejectUndisputedChargeback));
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+
+        let result = engine.process_transaction(chargeback(1, 1001));
+        assert!(matches!(result, Err(Error::DisputeNotStarted(1001))));
+        let result = engine.process_transaction(chargeback(1, 9999));
+        assert!(matches!(result, Err(Error::DisputeNotStarted(9999))));
+        assert!(!get_client_output(&engine, 1).locked);
+
+        // Disputed chargebacks are not affected
+        engine.process_transaction(dispute(1, 1001)).unwrap();
+        engine.process_transaction(chargeback(1, 1001)).unwrap();
+        assert!(get_client_output(&engine, 1).locked);
+    }
+}
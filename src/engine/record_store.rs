@@ -0,0 +1,561 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    engine::registry::{DisputeStatus, TransactionKind, TransactionRecord},
+    error::Error,
+};
+
+/// Storage for the transaction records of an [`Engine`](crate::Engine), keyed by transaction ID.
+///
+/// Records are written when a deposit or withdrawal is accepted and rewritten when its dispute
+/// status changes. Unless a dispute window is configured, they are never removed, so the store
+/// grows with the number of accepted transactions; [`DiskRecordStore`] keeps that growth out of
+/// memory.
+pub trait RecordStore: Send {
+    fn get(&self, transaction_id: u32) -> Result<Option<TransactionRecord>, Error>;
+
+    /// Inserts or replaces the record of a transaction.
+    fn insert(&mut self, transaction_id: u32, record: TransactionRecord) -> Result<(), Error>;
+
+    /// Removes the record of a transaction, if any.
+    fn remove(&mut self, transaction_id: u32) -> Result<(), Error>;
+
+    /// Calls `f` with every record, in no particular order, stopping at the first error.
+    fn for_each(
+        &self,
+        f: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+}
+
+/// Keeps every record in memory. The default store.
+#[derive(Default)]
+pub struct MemoryRecordStore {
+    record_map: HashMap<u32, TransactionRecord>,
+}
+
+impl MemoryRecordStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RecordStore for MemoryRecordStore {
+    fn get(&self, transaction_id: u32) -> Result<Option<TransactionRecord>, Error> {
+        Ok(self.record_map.get(&transaction_id).cloned())
+    }
+
+    fn insert(&mut self, transaction_id: u32, record: TransactionRecord) -> Result<(), Error> {
+        self.record_map.insert(transaction_id, record);
+        Ok(())
+    }
+
+    fn remove(&mut self, transaction_id: u32) -> Result<(), Error> {
+        self.record_map.remove(&transaction_id);
+        Ok(())
+    }
+
+    fn for_each(
+        &self,
+        f: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        for (&transaction_id, record) in &self.record_map {
+            f(transaction_id, record.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// The size of a record on disk: kind, dispute status, client ID, amount, disputed amount,
+/// charged back amount and resolved flag.
+const SLOT_SIZE: u64 = 53;
+/// The approximate memory used by a cached record, including the cache's own bookkeeping.
+const CACHED_RECORD_SIZE: usize = 128;
+
+/// Keeps records in a file with a fixed-size slot per transaction ID, and the most recently
+/// used ones in a cache bounded by a memory limit.
+///
+/// The slot of a transaction is at an offset computed from its ID, so no index is kept in
+/// memory: memory use is bounded by the cache whatever the number of records. The file is
+/// sparse, so only the slots of seen IDs take disk space on file systems supporting it. It is
+/// scratch space, created empty and deleted when the store is dropped.
+pub struct DiskRecordStore {
+    path: PathBuf,
+    file: File,
+    cache: RefCell<RecordCache>,
+    /// The largest transaction ID inserted, so that removing a record never extends the file.
+    max_transaction_id: Option<u32>,
+}
+
+impl DiskRecordStore {
+    /// Creates the store file at `path`, replacing any existing file, with a cache using about
+    /// `memory_limit` bytes.
+    pub fn create(path: impl AsRef<Path>, memory_limit: usize) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(DiskRecordStore {
+            path,
+            file,
+            cache: RefCell::new(RecordCache::new((memory_limit / CACHED_RECORD_SIZE).max(1))),
+            max_transaction_id: None,
+        })
+    }
+
+    fn read_slot(&self, transaction_id: u32) -> Result<Option<TransactionRecord>, Error> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(u64::from(transaction_id) * SLOT_SIZE))?;
+        let mut slot = [0; SLOT_SIZE as usize];
+        match file.read_exact(&mut slot) {
+            Ok(()) => Ok(decode_slot(&slot)),
+            // Past the end of the file: no record was written at or after this ID
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_slot(&self, transaction_id: u32, record: &TransactionRecord) -> Result<(), Error> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(u64::from(transaction_id) * SLOT_SIZE))?;
+        file.write_all(&encode_slot(record))?;
+        Ok(())
+    }
+
+    /// Caches a record, writing the records it evicts to disk.
+    fn cache(
+        &self,
+        transaction_id: u32,
+        record: TransactionRecord,
+        dirty: bool,
+    ) -> Result<(), Error> {
+        let mut cache = self.cache.borrow_mut();
+        cache.insert(transaction_id, record, dirty);
+        // A record is only evicted once written, so a failed write leaves it cached
+        while let Some((transaction_id, entry)) = cache.over_capacity() {
+            if entry.dirty {
+                self.write_slot(transaction_id, &entry.record)?;
+            }
+            cache.remove(transaction_id);
+        }
+        Ok(())
+    }
+}
+
+impl RecordStore for DiskRecordStore {
+    fn get(&self, transaction_id: u32) -> Result<Option<TransactionRecord>, Error> {
+        if let Some(record) = self.cache.borrow_mut().get(transaction_id) {
+            return Ok(Some(record));
+        }
+        let record = self.read_slot(transaction_id)?;
+        if let Some(record) = &record {
+            self.cache(transaction_id, record.clone(), false)?;
+        }
+        Ok(record)
+    }
+
+    fn insert(&mut self, transaction_id: u32, record: TransactionRecord) -> Result<(), Error> {
+        self.max_transaction_id = self.max_transaction_id.max(Some(transaction_id));
+        self.cache(transaction_id, record, true)
+    }
+
+    fn remove(&mut self, transaction_id: u32) -> Result<(), Error> {
+        self.cache.borrow_mut().remove(transaction_id);
+        if self
+            .max_transaction_id
+            .is_some_and(|max| transaction_id <= max)
+        {
+            let mut file = &self.file;
+            file.seek(SeekFrom::Start(u64::from(transaction_id) * SLOT_SIZE))?;
+            file.write_all(&[0; SLOT_SIZE as usize])?;
+        }
+        Ok(())
+    }
+
+    fn for_each(
+        &self,
+        f: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        // Cached records may be newer than the file, or not written yet, so they are visited
+        // instead of their slots
+        let cached = self.cache.borrow().records();
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut slot = [0; SLOT_SIZE as usize];
+        for transaction_id in 0..=u32::MAX {
+            match reader.read_exact(&mut slot) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            if let Some(record) = decode_slot(&slot)
+                && !cached.contains_key(&transaction_id)
+            {
+                f(transaction_id, record)?;
+            }
+        }
+        for (transaction_id, record) in cached {
+            f(transaction_id, record)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskRecordStore {
+    fn drop(&mut self) {
+        // The file only holds scratch data
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn encode_slot(record: &TransactionRecord) -> [u8; SLOT_SIZE as usize] {
+    let mut slot = [0; SLOT_SIZE as usize];
+    // A zero kind marks an empty slot, as read from the holes of the sparse file
+    slot[0] = match record.kind {
+        TransactionKind::Deposit => 1,
+        TransactionKind::Withdrawal => 2,
+    };
+    slot[1] = match record.dispute_status {
+        DisputeStatus::NotStarted => 0,
+        DisputeStatus::InProgress => 1,
+        DisputeStatus::Resolved => 2,
+        DisputeStatus::Chargebacked => 3,
+    };
+    slot[2..4].copy_from_slice(&record.client_id.to_le_bytes());
+    slot[4..20].copy_from_slice(&Decimal::from(record.amount).serialize());
+    slot[20..36].copy_from_slice(&Decimal::from(record.disputed).serialize());
+    slot[36..52].copy_from_slice(&Decimal::from(record.charged_back).serialize());
+    slot[52] = u8::from(record.resolved);
+    slot
+}
+
+fn decode_slot(slot: &[u8; SLOT_SIZE as usize]) -> Option<TransactionRecord> {
+    let kind = match slot[0] {
+        1 => TransactionKind::Deposit,
+        2 => TransactionKind::Withdrawal,
+        _ => return None,
+    };
+    let dispute_status = match slot[1] {
+        1 => DisputeStatus::InProgress,
+        2 => DisputeStatus::Resolved,
+        3 => DisputeStatus::Chargebacked,
+        _ => DisputeStatus::NotStarted,
+    };
+    let decimal_at = |offset: usize| {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&slot[offset..offset + 16]);
+        Decimal::deserialize(bytes).into()
+    };
+    Some(TransactionRecord {
+        client_id: u16::from_le_bytes([slot[2], slot[3]]),
+        kind,
+        amount: decimal_at(4),
+        dispute_status,
+        disputed: decimal_at(20),
+        charged_back: decimal_at(36),
+        resolved: slot[52] != 0,
+    })
+}
+
+/// A least-recently-used cache of records. Records inserted as dirty must be written to disk
+/// before they are evicted.
+struct RecordCache {
+    capacity: usize,
+    entries: HashMap<u32, CacheEntry>,
+    /// Transaction IDs by last use.
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+}
+
+struct CacheEntry {
+    record: TransactionRecord,
+    dirty: bool,
+    last_use: u64,
+}
+
+impl RecordCache {
+    fn new(capacity: usize) -> Self {
+        RecordCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, transaction_id: u32) -> Option<TransactionRecord> {
+        let entry = self.entries.get_mut(&transaction_id)?;
+        self.recency.remove(&entry.last_use);
+        self.tick += 1;
+        entry.last_use = self.tick;
+        self.recency.insert(self.tick, transaction_id);
+        Some(entry.record.clone())
+    }
+
+    fn remove(&mut self, transaction_id: u32) {
+        if let Some(entry) = self.entries.remove(&transaction_id) {
+            self.recency.remove(&entry.last_use);
+        }
+    }
+
+    /// A copy of every cached record.
+    fn records(&self) -> HashMap<u32, TransactionRecord> {
+        self.entries
+            .iter()
+            .map(|(&transaction_id, entry)| (transaction_id, entry.record.clone()))
+            .collect()
+    }
+
+    /// The least recently used record, while the cache holds more than its capacity.
+    fn over_capacity(&self) -> Option<(u32, &CacheEntry)> {
+        if self.entries.len() <= self.capacity {
+            return None;
+        }
+        let (_, &transaction_id) = self.recency.first_key_value()?;
+        Some((transaction_id, self.entries.get(&transaction_id)?))
+    }
+
+    fn insert(&mut self, transaction_id: u32, record: TransactionRecord, dirty: bool) {
+        self.tick += 1;
+        let entry = CacheEntry {
+            record,
+            dirty,
+            last_use: self.tick,
+        };
+        if let Some(previous) = self.entries.insert(transaction_id, entry) {
+            self.recency.remove(&previous.last_use);
+            // A record that was not written yet stays dirty
+            if let Some(entry) = self.entries.get_mut(&transaction_id) {
+                entry.dirty |= previous.dirty;
+            }
+        }
+        self.recency.insert(self.tick, transaction_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::engine::{Engine, LedgerAccount, MemoryAuditSink};
+
+    #[test]
+    fn test_disk_record_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records");
+        // Room for two cached records, so most reads go to disk
+        let mut store = DiskRecordStore::create(&path, 2 * CACHED_RECORD_SIZE).unwrap();
+
+        let record =
+            |client_id, kind, amount| TransactionRecord::new(client_id, kind, decimal(amount));
+        for transaction_id in [5, 1, 1_000_000, 3] {
+            store
+                .insert(
+                    transaction_id,
+                    record(transaction_id as u16, TransactionKind::Deposit, "1.5"),
+                )
+                .unwrap();
+        }
+        let mut disputed = record(1, TransactionKind::Withdrawal, "-2.25");
+        disputed.dispute_status = DisputeStatus::Chargebacked;
+        disputed.disputed = decimal("0.75");
+        disputed.charged_back = decimal("1.5");
+        disputed.resolved = true;
+        store.insert(1, disputed).unwrap();
+
+        store.remove(5).unwrap();
+        assert!(store.get(5).unwrap().is_none());
+        assert!(store.get(2).unwrap().is_none());
+        assert!(store.get(2_000_000).unwrap().is_none());
+        let record = store.get(1).unwrap().unwrap();
+        assert_eq!(record.kind, TransactionKind::Withdrawal);
+        assert_eq!(record.dispute_status, DisputeStatus::Chargebacked);
+        assert_eq!(record.amount, decimal("-2.25"));
+        assert_eq!(record.disputed, decimal("0.75"));
+        assert_eq!(record.charged_back, decimal("1.5"));
+        assert!(record.resolved);
+        let record = store.get(1_000_000).unwrap().unwrap();
+        assert_eq!(record.client_id, 16960);
+        assert_eq!(record.amount, decimal("1.5"));
+
+        let mut transaction_ids = Vec::new();
+        store
+            .for_each(&mut |transaction_id, _| {
+                transaction_ids.push(transaction_id);
+                Ok(())
+            })
+            .unwrap();
+        transaction_ids.sort();
+        assert_eq!(transaction_ids, [1, 3, 1_000_000]);
+
+        drop(store);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_disk_record_store_for_each_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store =
+            DiskRecordStore::create(dir.path().join("records"), 2 * CACHED_RECORD_SIZE).unwrap();
+        let record = |amount| TransactionRecord::new(1, TransactionKind::Deposit, decimal(amount));
+        store.insert(1, record("1.0")).unwrap();
+        store.insert(2, record("2.0")).unwrap();
+        // Evicts record 1 to disk, while the largest ID is only cached
+        store.insert(u32::MAX, record("3.0")).unwrap();
+        store.insert(1, record("4.0")).unwrap();
+
+        let mut records = Vec::new();
+        store
+            .for_each(&mut |transaction_id, record| {
+                records.push((transaction_id, record.amount));
+                Ok(())
+            })
+            .unwrap();
+        records.sort();
+        assert_eq!(
+            records,
+            [
+                (1, decimal("4.0")),
+                (2, decimal("2.0")),
+                (u32::MAX, decimal("3.0"))
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_disk_record_store_failed_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("records");
+        // Writes fail with a full disk, and reads only find empty slots
+        std::os::unix::fs::symlink("/dev/full", &path).unwrap();
+        let mut store = DiskRecordStore::create(&path, CACHED_RECORD_SIZE).unwrap();
+        let record = |amount| TransactionRecord::new(1, TransactionKind::Deposit, decimal(amount));
+
+        store.insert(1, record("1.0")).unwrap();
+        assert!(matches!(store.insert(2, record("2.0")), Err(Error::Io(_))));
+        // The record that could not be written is still cached
+        assert_eq!(store.get(1).unwrap().unwrap().amount, decimal("1.0"));
+    }
+
+    #[test]
+    fn test_engine_with_disk_record_store() {
+        let transactions = || {
+            (0..500u32).map(|i| {
+                let client = (i % 5) as u16;
+                match i % 10 {
+                    7 => dispute(client, i - 5),
+                    8 => resolve(client, i - 6),
+                    9 => chargeback(client, i - 9),
+                    4 => withdrawal(client, i, "1.0"),
+                    _ => deposit(client, i, "2.0"),
+                }
+            })
+        };
+
+        let mut memory = Engine::new();
+        let dir = tempfile::tempdir().unwrap();
+        let mut disk = Engine::new();
+        disk.set_record_store(Box::new(
+            DiskRecordStore::create(dir.path().join("records"), 4 * CACHED_RECORD_SIZE).unwrap(),
+        ))
+        .unwrap();
+        for tx in transactions() {
+            let expected = memory.process_transaction(tx.clone()).map_err(|e| e.code());
+            assert_eq!(disk.process_transaction(tx).map_err(|e| e.code()), expected);
+        }
+
+        let memory_output = get_client_output_map(&memory);
+        for (client, actual) in get_client_output_map(&disk) {
+            let expected = &memory_output[&client];
+            assert_eq!(actual.available, expected.available);
+            assert_eq!(actual.held, expected.held);
+            assert_eq!(actual.locked, expected.locked);
+        }
+        let summaries = |engine: &Engine| {
+            let mut summaries: Vec<_> = engine
+                .client_transactions(3)
+                .unwrap()
+                .into_iter()
+                .map(|summary| (summary.tx, summary.amount, summary.dispute_status))
+                .collect();
+            summaries.sort_by_key(|summary| summary.0);
+            summaries
+        };
+        assert_eq!(summaries(&disk), summaries(&memory));
+    }
+
+    /// A store whose disk is full.
+    struct FullStore;
+
+    impl RecordStore for FullStore {
+        fn get(&self, _: u32) -> Result<Option<TransactionRecord>, Error> {
+            Ok(None)
+        }
+
+        fn insert(&mut self, _: u32, _: TransactionRecord) -> Result<(), Error> {
+            Err(Error::Io(std::io::ErrorKind::StorageFull.into()))
+        }
+
+        fn remove(&mut self, _: u32) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn for_each(
+            &self,
+            _: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+        ) -> Result<(), Error> {
+            Err(Error::Io(std::io::ErrorKind::StorageFull.into()))
+        }
+    }
+
+    #[test]
+    fn test_record_store_failure() {
+        let mut engine = Engine::new();
+        engine.set_record_store(Box::new(FullStore)).unwrap();
+        let sink = MemoryAuditSink::new();
+        engine.set_audit_sink(Box::new(sink.clone()));
+
+        // A record that cannot be written leaves the account and the ledger untouched, and is
+        // not audited
+        let result = engine.process_transaction(deposit(1, 1, "10.00"));
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(get_client_output_map(&engine).is_empty());
+        assert!(sink.events().is_empty());
+        assert_eq!(ledger_balance(&engine, LedgerAccount::Cash), decimal("0"));
+        engine.trial_balance().unwrap();
+
+        // The store error is returned by the snapshot
+        let result = engine.save_snapshot(Vec::new());
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_record_store_failure_after_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let mut engine = Engine::recover(&path).unwrap();
+        engine.set_record_store(Box::new(FullStore)).unwrap();
+
+        // The deposit was journaled but not applied, so the engine refuses to go on
+        let result = engine.process_transaction(deposit(1, 1, "10.00"));
+        assert!(matches!(result, Err(Error::Io(_))));
+        let result = engine.process_transaction(deposit(2, 2, "5.00"));
+        assert!(matches!(result, Err(Error::EngineFailed)));
+        drop(engine);
+
+        let engine = Engine::recover(&path).unwrap();
+        assert_eq!(engine.transaction_count(), 1);
+        assert_eq!(get_client_output(&engine, 1).available, decimal("10.00"));
+        assert!(engine.account(2).is_none());
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{
+        checked_decimal::CheckedDecimal,
+        config::{ClientTransactions, EngineConfig, WithdrawalDisputes},
+        record_store::{MemoryRecordStore, RecordStore},
+    },
+    error::Error,
+};
+
+/// Records every deposit and withdrawal accepted by the engine, keyed by transaction ID.
+///
+/// Transaction IDs are globally unique, so the registry is shared by all clients. It is used both to
+/// reject reused IDs and to find the transaction referenced by a dispute, resolve or chargeback.
+/// The records themselves are kept in a [`RecordStore`], in memory by default.
+///
+/// With a dispute window, records are evicted once their window has passed. Only the ID of an
+/// evicted record is kept, in a bitmap, so that it stays claimed and disputes against it can be
+/// told apart from disputes against unknown transactions. Its client is not kept: reusing the ID
+/// is rejected as a duplicate, and disputing it as expired, whichever client does it.
+pub struct TransactionRegistry {
+    store: Box<dyn RecordStore>,
+    /// The records that have not expired yet, by the window position they were accepted at.
+    /// Only kept when a dispute window is configured.
+    window: BTreeSet<(u64, u32)>,
+    /// The window positions of the records in `window`, by transaction ID.
+    window_positions: HashMap<u32, u64>,
+    /// The IDs of the expired records.
+    expired: IdSet,
+    /// The IDs of the records in the store, by client. Only kept with
+    /// [`ClientTransactions::Indexed`].
+    clients: Option<HashMap<u16, BTreeSet<u32>>>,
+}
+
+impl TransactionRegistry {
+    pub fn new(client_transactions: ClientTransactions) -> Self {
+        TransactionRegistry {
+            store: Box::new(MemoryRecordStore::new()),
+            window: BTreeSet::new(),
+            window_positions: HashMap::new(),
+            expired: IdSet::default(),
+            clients: match client_transactions {
+                ClientTransactions::Scanned => None,
+                ClientTransactions::Indexed => Some(HashMap::new()),
+            },
+        }
+    }
+
+    /// Moves the records into `store`, which keeps them from now on.
+    pub fn set_store(&mut self, store: Box<dyn RecordStore>) -> Result<(), Error> {
+        let previous = std::mem::replace(&mut self.store, store);
+        previous.for_each(&mut |transaction_id, record| self.store.insert(transaction_id, record))
+    }
+
+    /// Checks that `transaction_id` has not been used yet, by this client or any other.
+    pub fn check_unused(&self, client_id: u16, transaction_id: u32) -> Result<(), Error> {
+        let owner = match self.store.get(transaction_id)? {
+            Some(record) => Some(record.client_id),
+            None if self.expired.contains(transaction_id) => {
+                return Err(Error::DuplicateTransactionId(transaction_id));
+            }
+            None => None,
+        };
+        match owner {
+            None => Ok(()),
+            Some(owner) if owner == client_id => Err(Error::DuplicateTransactionId(transaction_id)),
+            Some(_) => Err(Error::TransactionOwnedByAnotherClient(transaction_id)),
+        }
+    }
+
+    /// Records an accepted transaction, or updates the record of a disputed one.
+    ///
+    /// The record of an expired transaction is only kept while its dispute is in progress.
+    pub fn insert(&mut self, transaction_id: u32, record: TransactionRecord) -> Result<(), Error> {
+        if self.expired.contains(transaction_id)
+            && record.dispute_status != DisputeStatus::InProgress
+        {
+            self.unindex(record.client_id, transaction_id);
+            return self.store.remove(transaction_id);
+        }
+        self.index(record.client_id, transaction_id);
+        self.store.insert(transaction_id, record)
+    }
+
+    /// Looks up a transaction of the given client.
+    ///
+    /// Returns `Ok(None)` if the ID is unknown, and an error if it belongs to another client or
+    /// its record expired. An expired record is reported as such to any client.
+    pub fn find(
+        &self,
+        client_id: u16,
+        transaction_id: u32,
+    ) -> Result<Option<TransactionRecord>, Error> {
+        let owner = match self.store.get(transaction_id)? {
+            Some(record) if record.client_id == client_id => return Ok(Some(record)),
+            Some(record) => record.client_id,
+            None if self.expired.contains(transaction_id) => client_id,
+            None => return Ok(None),
+        };
+        if owner != client_id {
+            return Err(Error::TransactionOwnedByAnotherClient(transaction_id));
+        }
+        Err(Error::DisputeWindowExpired(transaction_id))
+    }
+
+    /// Starts the dispute window of a newly accepted transaction at `position`.
+    pub fn open_window(&mut self, position: u64, transaction_id: u32) {
+        self.window.insert((position, transaction_id));
+        self.window_positions.insert(transaction_id, position);
+    }
+
+    /// Whether the window of a transaction started before `position`, even if its record was
+    /// not evicted yet.
+    pub fn window_closed(&self, transaction_id: u32, position: u64) -> bool {
+        self.window_positions
+            .get(&transaction_id)
+            .is_some_and(|&opened_at| opened_at < position)
+    }
+
+    /// Evicts the records whose window started before `position`.
+    ///
+    /// A record under dispute is kept until the dispute is resolved or charged back, but can
+    /// no longer be disputed again.
+    pub fn expire(&mut self, position: u64) -> Result<(), Error> {
+        while let Some(&(opened_at, transaction_id)) = self.window.first() {
+            if opened_at >= position {
+                break;
+            }
+            self.window.pop_first();
+            self.window_positions.remove(&transaction_id);
+            let Some(record) = self.store.get(transaction_id)? else {
+                continue;
+            };
+            self.expired.insert(transaction_id);
+            if record.dispute_status != DisputeStatus::InProgress {
+                self.unindex(record.client_id, transaction_id);
+                self.store.remove(transaction_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The transactions whose dispute window is open, with the position it was opened at.
+    pub fn window(&self) -> impl Iterator<Item = (u64, u32)> + '_ {
+        self.window.iter().copied()
+    }
+
+    /// The IDs of the expired transactions, as ranges of consecutive IDs in ascending order.
+    pub fn expired(&self) -> Vec<RangeInclusive<u32>> {
+        self.expired.ranges()
+    }
+
+    /// Restores expired transactions saved in a snapshot.
+    pub fn insert_expired(&mut self, transaction_ids: RangeInclusive<u32>) {
+        for transaction_id in transaction_ids {
+            self.expired.insert(transaction_id);
+        }
+    }
+
+    /// Moves the records of another registry into this one.
+    pub fn extend(&mut self, other: TransactionRegistry) -> Result<(), Error> {
+        other.for_each(&mut |transaction_id, record| self.insert(transaction_id, record))?;
+        self.window.extend(other.window);
+        self.window_positions.extend(other.window_positions);
+        self.expired.extend(other.expired);
+        Ok(())
+    }
+
+    /// Calls `f` with every record, in no particular order.
+    pub fn for_each(
+        &self,
+        f: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.store.for_each(f)
+    }
+
+    /// Calls `f` with every record of a client, in no particular order.
+    ///
+    /// Without the client index, every record is visited.
+    pub fn for_each_of_client(
+        &self,
+        client_id: u16,
+        f: &mut dyn FnMut(u32, TransactionRecord) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let Some(clients) = &self.clients else {
+            return self.store.for_each(&mut |transaction_id, record| {
+                if record.client_id == client_id {
+                    f(transaction_id, record)?;
+                }
+                Ok(())
+            });
+        };
+        for &transaction_id in clients.get(&client_id).into_iter().flatten() {
+            if let Some(record) = self.store.get(transaction_id)? {
+                f(transaction_id, record)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, client_id: u16, transaction_id: u32) {
+        if let Some(clients) = &mut self.clients {
+            clients.entry(client_id).or_default().insert(transaction_id);
+        }
+    }
+
+    fn unindex(&mut self, client_id: u16, transaction_id: u32) {
+        if let Some(clients) = &mut self.clients
+            && let Some(transactions) = clients.get_mut(&client_id)
+        {
+            transactions.remove(&transaction_id);
+            if transactions.is_empty() {
+                clients.remove(&client_id);
+            }
+        }
+    }
+}
+
+/// A set of transaction IDs, with a bit for each ID in blocks of 64 consecutive IDs. Takes a few
+/// bits per ID when the IDs are mostly consecutive, and at most 512 MiB.
+#[derive(Default)]
+struct IdSet {
+    blocks: BTreeMap<u32, u64>,
+}
+
+impl IdSet {
+    fn insert(&mut self, id: u32) {
+        *self.blocks.entry(id / 64).or_default() |= 1 << (id % 64);
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        self.blocks
+            .get(&(id / 64))
+            .is_some_and(|bits| bits & (1 << (id % 64)) != 0)
+    }
+
+    fn extend(&mut self, other: IdSet) {
+        for (block, bits) in other.blocks {
+            *self.blocks.entry(block).or_default() |= bits;
+        }
+    }
+
+    /// The IDs as ranges of consecutive IDs, in ascending order.
+    fn ranges(&self) -> Vec<RangeInclusive<u32>> {
+        let mut ranges: Vec<RangeInclusive<u32>> = Vec::new();
+        for (&block, &bits) in &self.blocks {
+            let mut bits = bits;
+            while bits != 0 {
+                let id = block * 64 + bits.trailing_zeros();
+                bits &= bits - 1;
+                match ranges.last_mut() {
+                    Some(range) if range.end().checked_add(1) == Some(id) => {
+                        *range = *range.start()..=id;
+                    }
+                    _ => ranges.push(id..=id),
+                }
+            }
+        }
+        ranges
+    }
+}
+
+/// A deposit or withdrawal accepted by the engine, as kept in a [`RecordStore`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct TransactionRecord {
+    pub client_id: u16,
+    pub kind: TransactionKind,
+    pub amount: CheckedDecimal,
+    pub dispute_status: DisputeStatus,
+    /// The part of the amount under dispute.
+    #[serde(default)]
+    pub disputed: CheckedDecimal,
+    /// The part of the amount charged back.
+    #[serde(default)]
+    pub charged_back: CheckedDecimal,
+    /// Whether a dispute of the transaction, or of a part of it, was resolved.
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+impl TransactionRecord {
+    pub fn new(client_id: u16, kind: TransactionKind, amount: CheckedDecimal) -> Self {
+        TransactionRecord {
+            client_id,
+            kind,
+            amount,
+            dispute_status: DisputeStatus::NotStarted,
+            disputed: CheckedDecimal::ZERO,
+            charged_back: CheckedDecimal::ZERO,
+            resolved: false,
+        }
+    }
+
+    /// Updates the dispute status once no part of the amount is under dispute anymore. The
+    /// transaction is only charged back once all of it is, otherwise the rest can still be
+    /// disputed.
+    pub(crate) fn settle(&mut self) {
+        if self.disputed != CheckedDecimal::ZERO {
+            return;
+        }
+        self.dispute_status = if self.charged_back == self.amount {
+            DisputeStatus::Chargebacked
+        } else if self.resolved {
+            DisputeStatus::Resolved
+        } else {
+            DisputeStatus::NotStarted
+        };
+    }
+
+    /// The part of the amount which is neither under dispute nor charged back.
+    pub fn undisputed_amount(&self) -> Result<CheckedDecimal, Error> {
+        self.amount
+            .checked_sub(self.disputed)?
+            .checked_sub(self.charged_back)
+    }
+
+    /// Whether the configuration allows disputing this kind of transaction.
+    pub fn disputable(&self, config: &EngineConfig) -> bool {
+        match self.kind {
+            TransactionKind::Deposit => true,
+            TransactionKind::Withdrawal => {
+                config.withdrawal_disputes == WithdrawalDisputes::Allowed
+            }
+        }
+    }
+}
+
+/// A transaction record with its ID, as written to the journal and snapshots.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordEntry {
+    pub transaction_id: u32,
+    #[serde(flatten)]
+    pub record: TransactionRecord,
+}
+
+/// The kind of a recorded transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// Where a recorded transaction is in the dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus {
+    NotStarted,
+    InProgress,
+    Resolved,
+    Chargebacked,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_set() {
+        let mut ids = IdSet::default();
+        for id in [3, 4, 5, 63, 64, 65, 200, u32::MAX - 1, u32::MAX] {
+            ids.insert(id);
+        }
+        let mut other = IdSet::default();
+        other.insert(6);
+        ids.extend(other);
+
+        assert!(ids.contains(64));
+        assert!(!ids.contains(66));
+        assert!(!ids.contains(0));
+        assert_eq!(
+            ids.ranges(),
+            [3..=6, 63..=65, 200..=200, u32::MAX - 1..=u32::MAX]
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    engine::{EngineTransaction, csv::CsvInputRecord},
+    error::Error,
+};
+
+/// Writes rejected input rows as CSV, one row per rejection:
+/// `file, line, type, client, tx, amount, timestamp, reason, code, category, message`.
+///
+/// `file` and `line` locate the row in the input. The `type`, `client`, `tx`, `amount`, `timestamp` and `reason` columns hold the input values, so a corrected row can
+/// be submitted again. `code` and `category` are [`Error::code`] and [`Error::category`],
+/// `message` is the error's `Display` output.
+pub struct RejectWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> RejectWriter<W> {
+    pub fn from_writer(w: W) -> Self {
+        RejectWriter {
+            writer: csv::Writer::from_writer(w),
+        }
+    }
+
+    /// Writes the rows without a header, to append them to a report that already has one.
+    pub fn appending(w: W) -> Self {
+        RejectWriter {
+            writer: csv::WriterBuilder::new().has_headers(false).from_writer(w),
+        }
+    }
+
+    /// Writes a row that was read successfully but rejected during validation or processing.
+    pub fn write_record(
+        &mut self,
+        file: &str,
+        record: &CsvInputRecord,
+        error: &Error,
+    ) -> Result<(), Error> {
+        self.write_row(RejectRow {
+            file,
+            line: record.line,
+            r#type: record.r#type.as_str(),
+            client: &record.client.to_string(),
+            tx: &record.tx.to_string(),
+            amount: record.amount.unwrap_or_default(),
+            timestamp: record.timestamp.unwrap_or_default(),
+            reason: record.reason.unwrap_or_default(),
+            code: error.code(),
+            category: error.category().as_str(),
+            message: &error.to_string(),
+        })
+    }
+
+    /// Writes a parsed transaction rejected during processing, read from `line` of `file`.
+    pub fn write_transaction(
+        &mut self,
+        file: &str,
+        line: u64,
+        transaction: &EngineTransaction,
+        error: &Error,
+    ) -> Result<(), Error> {
+        self.write_row(RejectRow {
+            file,
+            line,
+            r#type: transaction.transaction_type().as_str(),
+            client: &transaction.client_id().to_string(),
+            tx: &transaction.transaction_id().to_string(),
+            amount: &transaction
+                .amount()
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            timestamp: &transaction
+                .timestamp()
+                .map(|timestamp| timestamp.to_string())
+                .unwrap_or_default(),
+            reason: transaction.reason().unwrap_or_default(),
+            code: error.code(),
+            category: error.category().as_str(),
+            message: &error.to_string(),
+        })
+    }
+
+    /// Writes a row that could not be read, from its raw fields in input order.
+    pub fn write_raw<S: AsRef<str>>(
+        &mut self,
+        file: &str,
+        line: u64,
+        fields: impl IntoIterator<Item = S>,
+        error: &Error,
+    ) -> Result<(), Error> {
+        let fields: Vec<S> = fields.into_iter().take(6).collect();
+        let field = |index: usize| fields.get(index).map_or("", AsRef::as_ref);
+        self.write_row(RejectRow {
+            file,
+            line,
+            r#type: field(0),
+            client: field(1),
+            tx: field(2),
+            amount: field(3),
+            timestamp: field(4),
+            reason: field(5),
+            code: error.code(),
+            category: error.category().as_str(),
+            message: &error.to_string(),
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: RejectRow) -> Result<(), Error> {
+        self.writer.serialize(row).map_err(Error::WriteCsvRecord)
+    }
+}
+
+#[derive(Serialize)]
+struct RejectRow<'a> {
+    file: &'a str,
+    line: u64,
+    r#type: &'a str,
+    client: &'a str,
+    tx: &'a str,
+    amount: &'a str,
+    timestamp: &'a str,
+    reason: &'a str,
+    code: &'a str,
+    category: &'a str,
+    message: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::csv::CsvReader;
+    use crate::engine::{Engine, EngineTransaction};
+    use crate::error::ErrorCategory;
+
+    #[test]
+    fn test_reject_report() {
+        let csv_data = r#"type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 1, 2, -1.0
+unknown, 1, 3, 1.0
+withdrawal, 1, 4, 5.0
+"#;
+
+        let mut engine = Engine::new();
+        let mut reader = CsvReader::from_reader(csv_data.as_bytes()).unwrap();
+        let mut rejects = RejectWriter::from_writer(Vec::new());
+
+        loop {
+            let record = match reader.read_next() {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(e) => {
+                    rejects
+                        .write_raw("input.csv", reader.line(), reader.raw_fields(), &e)
+                        .unwrap();
+                    continue;
+                }
+            };
+            let result = EngineTransaction::parse_csv_record(&record)
+                .and_then(|tx| engine.process_transaction(tx));
+            if let Err(e) = result {
+                rejects.write_record("input.csv", &record, &e).unwrap();
+            }
+        }
+
+        let output = String::from_utf8(rejects.writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "file,line,type,client,tx,amount,timestamp,reason,code,category,message"
+        );
+        assert_eq!(
+            lines[1],
+            "input.csv,3,deposit,1,2,-1.0,,,invalid_transaction_amount,input,Invalid transaction amount: Amount cannot be negative"
+        );
+        assert!(
+            lines[2].starts_with("input.csv,4,unknown,1,3,1.0,,,deserialize_csv_record,input,")
+        );
+        assert_eq!(
+            lines[3],
+            "input.csv,5,withdrawal,1,4,5.0,,,insufficient_funds,business_rule,Insufficient funds for transaction"
+        );
+    }
+
+    #[test]
+    fn test_reject_field_count() {
+        let csv_data = r#"type, client, tx, amount
+deposit, 1, 1
+deposit, 1, 2, 1.0, 2024-03-01T10:00:00Z
+deposit, 1, 3, 1.0
+"#;
+
+        let mut reader = CsvReader::from_reader(csv_data.as_bytes()).unwrap();
+        let mut rejects = RejectWriter::from_writer(Vec::new());
+        let mut read = Vec::new();
+
+        loop {
+            match reader.read_next() {
+                Ok(Some(record)) => read.push(record.tx),
+                Ok(None) => break,
+                Err(e) => rejects
+                    .write_raw("input.csv", reader.line(), reader.raw_fields(), &e)
+                    .unwrap(),
+            }
+        }
+
+        assert_eq!(read, vec![3]);
+        let output = String::from_utf8(rejects.writer.into_inner().unwrap()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines[1..],
+            [
+                "input.csv,2,deposit,1,1,,,,csv_field_count,input,\"Expected 4 CSV fields, found 3\"",
+                "input.csv,3,deposit,1,2,1.0,2024-03-01T10:00:00Z,,csv_field_count,input,\"Expected 4 CSV fields, found 5\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reject_invalid_utf8() {
+        let csv_data = b"type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1, 2, \xff\ndeposit, 1, 3, 1.0\n";
+
+        let mut reader = CsvReader::from_reader(&csv_data[..]).unwrap();
+        let mut read = Vec::new();
+        let mut errors = Vec::new();
+
+        loop {
+            match reader.read_next() {
+                Ok(Some(record)) => read.push(record.tx),
+                Ok(None) => break,
+                Err(e) => errors.push((reader.line(), e.code(), e.category())),
+            }
+        }
+
+        assert_eq!(read, vec![1, 3]);
+        assert_eq!(
+            errors,
+            vec![(3, "invalid_csv_record", ErrorCategory::Input)]
+        );
+    }
+}
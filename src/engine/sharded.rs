@@ -0,0 +1,474 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{
+    engine::{Engine, EngineConfig, EngineTransaction, Op},
+    error::Error,
+};
+
+/// The number of transactions sent to a worker at once.
+const BATCH_SIZE: usize = 256;
+/// The number of batches that can be queued for a worker before `submit` blocks.
+const QUEUE_BATCHES: usize = 64;
+/// How often a dispatcher waiting for a worker checks that it is still running.
+const WORKER_POLL: Duration = Duration::from_millis(100);
+
+/// A multi-threaded engine that routes transactions to worker threads by client ID.
+///
+/// Each worker owns an [`Engine`] holding a disjoint set of clients, so the transactions of a
+/// client are always processed by the same worker, in submission order. [`ShardedEngine::finish`]
+/// merges the workers' state into a single [`Engine`].
+///
+/// Like with [`Engine`], a transaction ID is only claimed by an accepted deposit or withdrawal.
+/// A transaction referencing an ID submitted by another client waits for the worker of that
+/// client to accept or reject it, so the outcome does not depend on thread scheduling.
+///
+/// A [dispute window](crate::DisputeWindow) counting transactions only counts those of the
+/// worker's clients, so records stay disputable for longer than with [`Engine`].
+pub struct ShardedEngine {
+    shards: Vec<Shard>,
+    /// The deposit or withdrawal claiming each transaction ID, until its worker processes it.
+    claims: HashMap<u32, Claim>,
+    /// The client owning each transaction ID, once its worker accepted the claiming deposit or
+    /// withdrawal.
+    owners: HashMap<u32, u16>,
+    events: Receiver<WorkerEvent>,
+    /// The rejections received from the workers and not collected yet.
+    rejections: Vec<Rejection>,
+}
+
+struct Shard {
+    sender: SyncSender<Vec<Job>>,
+    batch: Vec<Job>,
+    worker: JoinHandle<Engine>,
+    /// The number of jobs submitted to the worker.
+    submitted: u64,
+    /// The number of jobs the worker reported as processed.
+    processed: u64,
+    /// The jobs claiming a transaction ID and not processed yet, in submission order.
+    claiming: VecDeque<(u64, u32)>,
+}
+
+/// A deposit or withdrawal claiming its transaction ID: the job with this number in the worker
+/// of `shard`. The claim holds once the worker processed the job without rejecting it, and the
+/// client then owns the ID.
+#[derive(Clone, Copy)]
+struct Claim {
+    client_id: u16,
+    shard: usize,
+    job: u64,
+}
+
+enum WorkerEvent {
+    Rejected {
+        shard: usize,
+        job: u64,
+        rejection: Rejection,
+    },
+    /// The worker processed its first `jobs` jobs.
+    Processed { shard: usize, jobs: u64 },
+}
+
+struct Job {
+    tag: u64,
+    transaction: EngineTransaction,
+    /// Set when the transaction ID is claimed by a client of another worker.
+    claimed_elsewhere: bool,
+}
+
+/// A transaction rejected by a worker of a [`ShardedEngine`].
+#[derive(Debug)]
+pub struct Rejection {
+    /// The tag passed to [`ShardedEngine::submit`].
+    pub tag: u64,
+    pub transaction: EngineTransaction,
+    pub error: Error,
+}
+
+impl ShardedEngine {
+    /// Starts `threads` workers (at least one), each with its own engine using `config`.
+    pub fn new(config: EngineConfig, threads: usize) -> Self {
+        let (event_sender, events) = mpsc::channel();
+        let shards = (0..threads.max(1))
+            .map(|index| {
+                let (sender, receiver) = mpsc::sync_channel(QUEUE_BATCHES);
+                let engine = Engine::with_config(config.clone());
+                let event_sender = event_sender.clone();
+                let worker =
+                    thread::spawn(move || run_worker(index, engine, receiver, event_sender));
+                Shard {
+                    sender,
+                    batch: Vec::with_capacity(BATCH_SIZE),
+                    worker,
+                    submitted: 0,
+                    processed: 0,
+                    claiming: VecDeque::new(),
+                }
+            })
+            .collect();
+
+        ShardedEngine {
+            shards,
+            claims: HashMap::new(),
+            owners: HashMap::new(),
+            events,
+            rejections: Vec::new(),
+        }
+    }
+
+    /// Queues a transaction for processing. `tag` identifies the transaction in the
+    /// [`Rejection`] reported if it is rejected, e.g. the input line number.
+    ///
+    /// Rejections are reported asynchronously through [`ShardedEngine::rejections`].
+    pub fn submit(&mut self, tag: u64, transaction: EngineTransaction) {
+        let client_id = transaction.client_id;
+        let transaction_id = transaction.transaction_id();
+        let index = usize::from(client_id) % self.shards.len();
+        let owner = match &transaction.op {
+            Op::Deposit(_) | Op::Withdrawal(_) => {
+                let owner = self.owner(transaction_id, client_id, true);
+                if owner.is_none() {
+                    let job = self.shards[index].submitted;
+                    self.claims.insert(
+                        transaction_id,
+                        Claim {
+                            client_id,
+                            shard: index,
+                            job,
+                        },
+                    );
+                    self.shards[index].claiming.push_back((job, transaction_id));
+                }
+                owner
+            }
+            Op::Dispute(_) | Op::Resolve(_) | Op::Chargeback(_) => {
+                self.owner(transaction_id, client_id, false)
+            }
+            // Admin action IDs are not transaction IDs
+            Op::Unlock(_) | Op::Freeze(_) | Op::Close(_) | Op::Adjustment(_) => None,
+        };
+        // The worker rejects the transaction with the same error as `Engine`, and itself rejects
+        // a duplicate ID of the client's own transaction
+        let claimed_elsewhere = owner.is_some_and(|owner| owner != client_id);
+
+        let shard = &mut self.shards[index];
+        shard.batch.push(Job {
+            tag,
+            transaction,
+            claimed_elsewhere,
+        });
+        shard.submitted += 1;
+        if shard.batch.len() >= BATCH_SIZE {
+            shard.flush();
+        }
+    }
+
+    /// The client whose accepted deposit or withdrawal claimed `transaction_id`, if any.
+    ///
+    /// A claim of another client than `client_id`, or of any client if `settle_own` is set, is
+    /// settled first by waiting for its worker to process it. IDs are rarely reused, so this
+    /// seldom waits.
+    fn owner(&mut self, transaction_id: u32, client_id: u16, settle_own: bool) -> Option<u16> {
+        if let Some(&owner) = self.owners.get(&transaction_id) {
+            return Some(owner);
+        }
+        let claim = *self.claims.get(&transaction_id)?;
+        if claim.client_id == client_id && !settle_own {
+            return Some(client_id);
+        }
+        if self.shards[claim.shard].processed <= claim.job {
+            self.shards[claim.shard].flush();
+        }
+        while self.shards[claim.shard].processed <= claim.job {
+            match self.events.recv_timeout(WORKER_POLL) {
+                Ok(event) => self.handle(event),
+                // A worker that panicked never reports its jobs; `finish` reports the panic
+                Err(RecvTimeoutError::Timeout)
+                    if !self.shards[claim.shard].worker.is_finished() => {}
+                Err(_) => break,
+            }
+        }
+        self.owners.get(&transaction_id).copied()
+    }
+
+    fn handle(&mut self, event: WorkerEvent) {
+        match event {
+            WorkerEvent::Rejected {
+                shard,
+                job,
+                rejection,
+            } => {
+                // A rejected deposit or withdrawal gives up its claim
+                let transaction_id = rejection.transaction.transaction_id();
+                if self
+                    .claims
+                    .get(&transaction_id)
+                    .is_some_and(|claim| claim.shard == shard && claim.job == job)
+                {
+                    self.claims.remove(&transaction_id);
+                }
+                self.rejections.push(rejection);
+            }
+            WorkerEvent::Processed { shard: index, jobs } => {
+                let Some(shard) = self.shards.get_mut(index) else {
+                    return;
+                };
+                shard.processed = jobs;
+                // The claims of the processed jobs that were not rejected hold
+                while let Some(&(job, transaction_id)) = shard.claiming.front()
+                    && job < jobs
+                {
+                    shard.claiming.pop_front();
+                    if let Some(claim) = self.claims.get(&transaction_id)
+                        && claim.shard == index
+                        && claim.job == job
+                    {
+                        self.owners.insert(transaction_id, claim.client_id);
+                        self.claims.remove(&transaction_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the rejections reported by the workers so far, without waiting for more.
+    pub fn rejections(&mut self) -> impl Iterator<Item = Rejection> + '_ {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+        self.rejections.drain(..)
+    }
+
+    /// Waits for the workers to process every submitted transaction, and merges their state into a
+    /// single engine. Returns the merged engine and the rejections not yet collected.
+    pub fn finish(mut self) -> Result<(Engine, Vec<Rejection>), Error> {
+        let mut engines = mem::take(&mut self.shards).into_iter().map(|mut shard| {
+            shard.flush();
+            // Closing the channel stops the worker
+            drop(shard.sender);
+            match shard.worker.join() {
+                Ok(engine) => engine,
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        });
+
+        let mut engine = engines.next().unwrap_or_default();
+        for other in engines {
+            engine.merge(other)?;
+        }
+        let rejections: Vec<_> = self.rejections().collect();
+        Ok((engine, rejections))
+    }
+}
+
+impl Shard {
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let batch = mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        // The worker only stops when the sender is dropped, or if it panicked, which `finish`
+        // reports when joining it.
+        let _ = self.sender.send(batch);
+    }
+}
+
+fn run_worker(
+    shard: usize,
+    mut engine: Engine,
+    receiver: Receiver<Vec<Job>>,
+    event_sender: Sender<WorkerEvent>,
+) -> Engine {
+    let mut job = 0;
+    for batch in receiver {
+        for Job {
+            tag,
+            transaction,
+            claimed_elsewhere,
+        } in batch
+        {
+            let result = engine.process_claimed(&transaction, claimed_elsewhere);
+            if let Err(error) = result {
+                let _ = event_sender.send(WorkerEvent::Rejected {
+                    shard,
+                    job,
+                    rejection: Rejection {
+                        tag,
+                        transaction,
+                        error,
+                    },
+                });
+            }
+            job += 1;
+        }
+        let _ = event_sender.send(WorkerEvent::Processed { shard, jobs: job });
+    }
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+
+    #[test]
+    fn test_sharded_matches_single_threaded() {
+        let transactions = || {
+            // Transaction `i` belongs to client `i % 7`: disputes reference the client's deposit
+            // seven transactions earlier, chargebacks the one disputed three weeks earlier
+            (0..1000u32).map(|i| {
+                let client = (i % 7) as u16;
+                match i % 10 {
+                    8 => dispute(client, i - 7),
+                    9 if i >= 28 => chargeback(client, i - 28),
+                    6..=7 => withdrawal(client, i, "3.0"),
+                    _ => deposit(client, i, "2.0"),
+                }
+            })
+        };
+
+        let mut single = Engine::new();
+        let mut single_rejections = 0;
+        for tx in transactions() {
+            if single.process_transaction(tx).is_err() {
+                single_rejections += 1;
+            }
+        }
+
+        let mut sharded = ShardedEngine::new(EngineConfig::default(), 3);
+        for (tag, tx) in transactions().enumerate() {
+            sharded.submit(tag as u64, tx);
+        }
+        let (merged, rejections) = sharded.finish().unwrap();
+
+        assert_eq!(rejections.len(), single_rejections);
+        assert_eq!(merged.transaction_count(), single.transaction_count());
+        let single_output = get_client_output_map(&single);
+        let merged_output = get_client_output_map(&merged);
+        assert_eq!(merged_output.len(), 7);
+        for (client, expected) in &single_output {
+            let actual = &merged_output[client];
+            assert_eq!(actual.available, expected.available);
+            assert_eq!(actual.held, expected.held);
+            assert_eq!(actual.locked, expected.locked);
+        }
+    }
+
+    #[test]
+    fn test_sharded_claims_like_single_threaded() {
+        let transactions = || {
+            // Withdrawals are often rejected, and deposits of locked accounts always are: the
+            // next client reuses their IDs, which are only claimed if they were accepted
+            (0..1000u32).map(|i| {
+                let client = (i % 5) as u16;
+                match i % 6 {
+                    0 => withdrawal(client, i, "7.0"),
+                    1 => deposit(client, i - 1, "2.0"),
+                    2 => dispute(client, i - 2),
+                    3 if i % 60 == 3 => chargeback(client, 100_000 + i),
+                    4 => deposit(client, i - 1, "1.0"),
+                    _ => deposit(client, i, "3.0"),
+                }
+            })
+        };
+
+        let mut single = Engine::new();
+        let mut single_rejections = Vec::new();
+        for (tag, tx) in transactions().enumerate() {
+            if let Err(error) = single.process_transaction(tx) {
+                single_rejections.push((tag as u64, error.code()));
+            }
+        }
+
+        let mut sharded = ShardedEngine::new(EngineConfig::default(), 3);
+        for (tag, tx) in transactions().enumerate() {
+            sharded.submit(tag as u64, tx);
+        }
+        let (merged, rejections) = sharded.finish().unwrap();
+
+        let mut rejections: Vec<_> = rejections
+            .iter()
+            .map(|rejection| (rejection.tag, rejection.error.code()))
+            .collect();
+        rejections.sort();
+        assert_eq!(rejections, single_rejections);
+        assert!(
+            rejections
+                .iter()
+                .any(|&(_, code)| code == "transaction_owned_by_another_client")
+        );
+        let single_output = get_client_output_map(&single);
+        let merged_output = get_client_output_map(&merged);
+        assert_eq!(merged_output.len(), single_output.len());
+        for (client, expected) in &single_output {
+            let actual = &merged_output[client];
+            assert_eq!(actual.available, expected.available);
+            assert_eq!(actual.held, expected.held);
+            assert_eq!(actual.locked, expected.locked);
+        }
+    }
+
+    #[test]
+    fn test_sharded_preserves_client_order() {
+        let mut sharded = ShardedEngine::new(EngineConfig::default(), 4);
+        sharded.submit(1, deposit(1, 1, "5.0"));
+        sharded.submit(2, withdrawal(1, 2, "5.0"));
+        sharded.submit(3, withdrawal(1, 3, "1.0"));
+        let (engine, rejections) = sharded.finish().unwrap();
+
+        assert_eq!(get_client_output(&engine, 1).available, decimal("0.0"));
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].tag, 3);
+        assert!(matches!(rejections[0].error, Error::InsufficientFunds));
+    }
+
+    #[test]
+    fn test_sharded_rejects_other_client_transaction() {
+        let mut sharded = ShardedEngine::new(EngineConfig::default(), 2);
+        sharded.submit(1, deposit(1, 1, "5.0"));
+        sharded.submit(2, deposit(2, 1, "5.0"));
+        sharded.submit(3, dispute(2, 1));
+        let (engine, mut rejections) = sharded.finish().unwrap();
+
+        rejections.sort_by_key(|rejection| rejection.tag);
+        assert_eq!(rejections.len(), 2);
+        for (rejection, tag) in rejections.iter().zip([2, 3]) {
+            assert_eq!(rejection.tag, tag);
+            assert!(matches!(
+                rejection.error,
+                Error::TransactionOwnedByAnotherClient(1)
+            ));
+        }
+        assert_eq!(get_client_output(&engine, 1).available, decimal("5.0"));
+        // The rejected client still gets an empty account, like with `Engine`
+        assert_eq!(get_client_output(&engine, 2).total, decimal("0"));
+        assert_eq!(engine.transaction_count(), 3);
+    }
+
+    #[test]
+    fn test_sharded_drops_settled_claims() {
+        let mut sharded = ShardedEngine::new(EngineConfig::default(), 2);
+        sharded.submit(1, deposit(1, 1, "5.0"));
+        sharded.submit(2, withdrawal(1, 2, "9.0"));
+        // Waits for the worker of client 1, which accepts the deposit and rejects the withdrawal
+        sharded.submit(3, deposit(2, 2, "1.0"));
+        sharded.submit(4, dispute(2, 1));
+
+        assert_eq!(sharded.owners, HashMap::from([(1, 1)]));
+        assert_eq!(sharded.claims.len(), 1);
+        assert_eq!(sharded.claims[&2].client_id, 2);
+
+        let (engine, mut rejections) = sharded.finish().unwrap();
+        rejections.sort_by_key(|rejection| rejection.tag);
+        assert_eq!(rejections.len(), 2);
+        assert!(matches!(rejections[0].error, Error::InsufficientFunds));
+        assert!(matches!(
+            rejections[1].error,
+            Error::TransactionOwnedByAnotherClient(1)
+        ));
+        assert_eq!(get_client_output(&engine, 2).available, decimal("1.0"));
+    }
+}
@@ -0,0 +1,415 @@
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeSeq};
+
+use crate::{
+    engine::{
+        AccountStatus, AdminAction, DisputeStatus, Engine, EngineConfig, HistoryEntry,
+        LedgerAccount, LedgerBalance, StatusReason,
+        account::Account,
+        checked_decimal::CheckedDecimal,
+        ledger::suspense_postings,
+        registry::{RecordEntry, TransactionRegistry},
+        timestamp::Timestamp,
+    },
+    error::Error,
+};
+
+/// The snapshot format version written by [`Engine::save_snapshot`].
+const SNAPSHOT_VERSION: u32 = 2;
+/// The version before account statuses and partial disputes, which can still be loaded.
+const SNAPSHOT_VERSION_1: u32 = 1;
+
+impl Engine {
+    /// Writes the full engine state as JSON: every account with its balance and status, and
+    /// every transaction record with its dispute status, and the admin operations accepted. With a
+    /// dispute window, it also holds the window of every record and the IDs of the expired ones.
+    /// The balances of the ledger accounts are saved, but not the [ledger entries](crate::Ledger::entries).
+    /// The [history](Engine::history) of the accounts is saved if it is kept.
+    ///
+    /// The configuration and the transaction count are not part of the snapshot.
+    pub fn save_snapshot<W: Write>(&self, w: W) -> Result<(), Error> {
+        let transactions = Records {
+            registry: &self.registry,
+            error: RefCell::new(None),
+        };
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts: self
+                .accounts()
+                .map(|(client, account)| SnapshotAccount {
+                    client,
+                    available: account.balance().available,
+                    held: account.balance().held,
+                    locked: false,
+                    status: Some(account.status()),
+                    status_reason: account.status_reason().cloned(),
+                    latest_timestamp: account.latest_timestamp(),
+                })
+                .collect(),
+            transactions,
+            window_position: self.window_offset + self.transaction_count,
+            latest_timestamp: self.latest_timestamp,
+            dispute_window: self
+                .registry
+                .window()
+                .map(|(opened_at, transaction_id)| WindowEntry {
+                    transaction_id,
+                    opened_at,
+                })
+                .collect(),
+            expired: Vec::new(),
+            expired_ranges: self
+                .registry
+                .expired()
+                .into_iter()
+                .map(|range| ExpiredRange {
+                    first: *range.start(),
+                    last: *range.end(),
+                })
+                .collect(),
+            admin_actions: self.admin_actions.clone(),
+            history: self
+                .history
+                .iter()
+                .flat_map(|history| history.values().flatten().cloned())
+                .collect(),
+            ledger: Some(
+                self.ledger
+                    .balances()
+                    .map(|(account, balance)| SnapshotLedgerAccount {
+                        account,
+                        balance: *balance,
+                    })
+                    .collect(),
+            ),
+        };
+        let result = serde_json::to_writer(w, &snapshot);
+        if let Some(err) = snapshot.transactions.error.take() {
+            return Err(err);
+        }
+        result.map_err(Error::WriteSnapshot)
+    }
+
+    /// Restores an engine saved with [`Engine::save_snapshot`], with the default configuration.
+    pub fn load_snapshot<R: Read>(r: R) -> Result<Self, Error> {
+        Self::load_snapshot_with_config(EngineConfig::default(), r)
+    }
+
+    /// Restores an engine saved with [`Engine::save_snapshot`].
+    pub fn load_snapshot_with_config<R: Read>(config: EngineConfig, r: R) -> Result<Self, Error> {
+        let snapshot: Snapshot = serde_json::from_reader(r).map_err(Error::ReadSnapshot)?;
+        if snapshot.version != SNAPSHOT_VERSION && snapshot.version != SNAPSHOT_VERSION_1 {
+            return Err(Error::UnsupportedSnapshotVersion(snapshot.version));
+        }
+        let legacy = snapshot.version == SNAPSHOT_VERSION_1;
+
+        let mut engine = Engine::with_config(config);
+        for account in snapshot.accounts {
+            // Version 1 snapshots written before account statuses only have the locked flag
+            let status = account.status.unwrap_or(if account.locked {
+                AccountStatus::Locked
+            } else {
+                AccountStatus::Active
+            });
+            let postings = suspense_postings(account.client, account.available, account.held)?;
+            let mut restored =
+                Account::restore(account.client, &postings, status, account.status_reason)?;
+            restored.observe_timestamp(account.latest_timestamp);
+            engine.accounts.insert(account.client, restored);
+            // Version 1 snapshots written before the ledger bring the balances from the suspense
+            // account
+            if snapshot.ledger.is_none() {
+                for posting in &postings {
+                    engine.ledger.post_balance(posting)?;
+                }
+            }
+        }
+        // The history is only restored if the configuration keeps it
+        if let Some(history) = &mut engine.history {
+            for entry in snapshot.history {
+                history.entry(entry.client).or_default().push(entry);
+            }
+        }
+        for entry in snapshot.ledger.into_iter().flatten() {
+            engine.ledger.restore(entry.account, entry.balance);
+        }
+        for entry in snapshot.transactions {
+            let mut record = entry.record;
+            // Version 1 snapshots written before partial disputes only dispute whole transactions,
+            // and do not flag the resolved ones
+            if legacy
+                && record.dispute_status == DisputeStatus::InProgress
+                && record.disputed == CheckedDecimal::ZERO
+            {
+                record.disputed = record.amount;
+            }
+            if legacy && record.dispute_status == DisputeStatus::Resolved {
+                record.resolved = true;
+            }
+            engine.registry.insert(entry.transaction_id, record)?;
+        }
+        engine.admin_actions = snapshot.admin_actions;
+        engine.window_offset = snapshot.window_position;
+        engine.latest_timestamp = snapshot.latest_timestamp;
+        for entry in snapshot.dispute_window {
+            engine
+                .registry
+                .open_window(entry.opened_at, entry.transaction_id);
+        }
+        for range in snapshot.expired_ranges {
+            engine.registry.insert_expired(range.first..=range.last);
+        }
+        for entry in snapshot.expired {
+            engine
+                .registry
+                .insert_expired(entry.transaction_id..=entry.transaction_id);
+        }
+        Ok(engine)
+    }
+}
+
+/// The transactions are a [`Records`] when the snapshot is written.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T = Vec<RecordEntry>> {
+    version: u32,
+    accounts: Vec<SnapshotAccount>,
+    transactions: T,
+    /// The dispute window position of the next transaction.
+    #[serde(default)]
+    window_position: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latest_timestamp: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dispute_window: Vec<WindowEntry>,
+    /// Only read from snapshots written before the expired IDs were saved as ranges.
+    #[serde(default, skip_serializing)]
+    expired: Vec<ExpiredEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    expired_ranges: Vec<ExpiredRange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    admin_actions: Vec<AdminAction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<HistoryEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ledger: Option<Vec<SnapshotLedgerAccount>>,
+}
+
+/// The records of a registry, serialized as they are read from the store instead of being
+/// collected first.
+struct Records<'a> {
+    registry: &'a TransactionRegistry,
+    /// The error of the store, if reading the records failed.
+    error: RefCell<Option<Error>>,
+}
+
+impl Serialize for Records<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        let mut serialize_error = None;
+        let result = self.registry.for_each(&mut |transaction_id, record| {
+            seq.serialize_element(&RecordEntry {
+                transaction_id,
+                record,
+            })
+            .map_err(|err| {
+                serialize_error = Some(err);
+                // Only stops the iteration: the serializer error is returned instead
+                Error::Io(std::io::Error::other("snapshot write failed"))
+            })
+        });
+        if let Some(err) = serialize_error {
+            return Err(err);
+        }
+        if let Err(err) = result {
+            let message = err.to_string();
+            *self.error.borrow_mut() = Some(err);
+            return Err(serde::ser::Error::custom(message));
+        }
+        seq.end()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WindowEntry {
+    transaction_id: u32,
+    opened_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotLedgerAccount {
+    account: LedgerAccount,
+    #[serde(flatten)]
+    balance: LedgerBalance,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExpiredEntry {
+    transaction_id: u32,
+}
+
+/// The IDs of expired transactions from `first` to `last`, both included.
+#[derive(Serialize, Deserialize)]
+struct ExpiredRange {
+    first: u32,
+    last: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotAccount {
+    client: u16,
+    available: CheckedDecimal,
+    held: CheckedDecimal,
+    /// Only read from version 1 snapshots, which have no status when written before account
+    /// statuses.
+    #[serde(default, skip_serializing)]
+    locked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<AccountStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status_reason: Option<StatusReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latest_timestamp: Option<Timestamp>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::engine::{DisputeWindow, SingleDisputePolicy};
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut engine = Engine::new();
+        engine
+            .process_transaction(deposit(1, 1001, "100.00"))
+            .unwrap();
+        engine
+            .process_transaction(deposit(2, 2001, "50.00"))
+            .unwrap();
+        engine
+            .process_transaction(withdrawal(2, 2002, "20.00"))
+            .unwrap();
+        engine.process_transaction(dispute(1, 1001)).unwrap();
+        engine
+            .process_transaction(deposit(3, 3001, "5.00"))
+            .unwrap();
+        engine.process_transaction(chargeback(3, 9999)).unwrap();
+
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let mut engine = Engine::load_snapshot(buf.as_slice()).unwrap();
+
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("0.00"));
+        assert_eq!(account1.held, decimal("100.00"));
+        assert_eq!(account1.total, decimal("100.00"));
+        let account2 = get_client_output(&engine, 2);
+        assert_eq!(account2.available, decimal("30.00"));
+        assert!(get_client_output(&engine, 3).locked);
+
+        // A dispute opened before the snapshot can be resolved after it
+        engine.process_transaction(resolve(1, 1001)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("100.00"));
+        assert_eq!(account1.held, decimal("0.00"));
+
+        // Transaction IDs are still claimed
+        let result = engine.process_transaction(deposit(1, 2002, "1.00"));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionOwnedByAnotherClient(2002))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_dispute_window() {
+        let config = || EngineConfig {
+            dispute_window: Some(DisputeWindow::Transactions(2)),
+            ..Default::default()
+        };
+        let mut engine = Engine::with_config(config());
+        engine.process_transaction(deposit(1, 1, "10.00")).unwrap();
+        engine.process_transaction(deposit(1, 2, "10.00")).unwrap();
+        engine.process_transaction(deposit(1, 3, "10.00")).unwrap();
+        engine.process_transaction(deposit(1, 4, "10.00")).unwrap();
+
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let snapshot = String::from_utf8(buf.clone()).unwrap();
+        assert!(snapshot.contains(r#""expired_ranges":[{"first":1,"last":1}]"#));
+        let mut engine = Engine::load_snapshot_with_config(config(), buf.as_slice()).unwrap();
+
+        // The window positions carry over the snapshot
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(1))));
+        engine.process_transaction(dispute(1, 4)).unwrap();
+        let result = engine.process_transaction(dispute(1, 3));
+        assert!(matches!(result, Err(Error::DisputeWindowExpired(3))));
+        let result = engine.process_transaction(deposit(2, 1, "1.00"));
+        assert!(matches!(result, Err(Error::DuplicateTransactionId(1))));
+
+        // Snapshots listing the expired transactions one by one can still be loaded
+        let snapshot = snapshot.replace(
+            r#""expired_ranges":[{"first":1,"last":1}]"#,
+            r#""expired":[{"transaction_id":1,"client":1}]"#,
+        );
+        let mut engine = Engine::load_snapshot_with_config(config(), snapshot.as_bytes()).unwrap();
+        let result = engine.process_transaction(deposit(2, 1, "1.00"));
+        assert!(matches!(result, Err(Error::DuplicateTransactionId(1))));
+    }
+
+    #[test]
+    fn test_snapshot_version_1() {
+        // Written before account statuses, the ledger and partial disputes
+        let snapshot = r#"{
+            "version": 1,
+            "accounts": [
+                {"client": 1, "available": "10.0", "held": "5.0", "locked": false},
+                {"client": 2, "available": "0", "held": "0", "locked": true}
+            ],
+            "transactions": [
+                {"transaction_id": 1, "client_id": 1, "kind": "deposit", "amount": "10.0",
+                 "dispute_status": "resolved"},
+                {"transaction_id": 2, "client_id": 1, "kind": "deposit", "amount": "5.0",
+                 "dispute_status": "in_progress"}
+            ]
+        }"#;
+        let config = EngineConfig {
+            dispute_policy: Arc::new(SingleDisputePolicy),
+            ..Default::default()
+        };
+        let mut engine = Engine::load_snapshot_with_config(config, snapshot.as_bytes()).unwrap();
+
+        assert_eq!(engine.account(1).unwrap().status(), AccountStatus::Active);
+        assert_eq!(engine.account(2).unwrap().status(), AccountStatus::Locked);
+        engine.trial_balance().unwrap();
+        // The whole deposit is disputed, and the resolved one cannot be disputed again
+        engine.process_transaction(resolve(1, 2)).unwrap();
+        let account1 = get_client_output(&engine, 1);
+        assert_eq!(account1.available, decimal("15.0"));
+        assert_eq!(account1.held, decimal("0.0"));
+        let result = engine.process_transaction(dispute(1, 1));
+        assert!(matches!(result, Err(Error::DisputeNotAllowed(1))));
+
+        // Saved again, it is a current snapshot
+        let mut buf = Vec::new();
+        engine.save_snapshot(&mut buf).unwrap();
+        let snapshot: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(snapshot["version"], SNAPSHOT_VERSION);
+        assert!(snapshot["accounts"][0].get("locked").is_none());
+    }
+
+    #[test]
+    fn test_snapshot_unsupported_version() {
+        let snapshot = r#"{"version":999,"accounts":[],"transactions":[]}"#;
+        let result = Engine::load_snapshot(snapshot.as_bytes());
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedSnapshotVersion(999))
+        ));
+    }
+}
@@ -0,0 +1,20 @@
+use crate::{engine::csv::CsvInputRecord, error::Error};
+
+/// A stream of input records, such as a [`CsvReader`] or a [`JsonLinesReader`].
+///
+/// [`CsvReader`]: crate::CsvReader
+/// [`JsonLinesReader`]: crate::JsonLinesReader
+pub trait TransactionSource {
+    /// Reads the next record, or returns `Ok(None)` at the end of the input.
+    ///
+    /// A record that cannot be deserialized is reported as an error, after which reading can
+    /// continue with the next record.
+    fn read_next(&mut self) -> Result<Option<CsvInputRecord<'_>>, Error>;
+
+    /// The line number of the last record read.
+    fn line(&self) -> u64;
+
+    /// The `type`, `client`, `tx`, `amount`, `timestamp` and `reason` values of the last record
+    /// read as far as they can be recovered, even if it could not be deserialized.
+    fn raw_fields(&self) -> Vec<String>;
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{engine::csv::TransactionType, error::Error};
+
+/// The state of an account, which decides the transactions it accepts.
+///
+/// | Status   | Accepts                                                                  |
+/// |----------|--------------------------------------------------------------------------|
+/// | `active` | every transaction                                                        |
+/// | `frozen` | deposits, disputes, resolves, chargebacks and admin operations           |
+/// | `locked` | admin operations                                                         |
+/// | `closed` | nothing                                                                  |
+///
+/// Other transactions are rejected with [`Error::AccountFrozen`], [`Error::AccountLocked`] or
+/// [`Error::AccountClosed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Under review: funds can come in and disputes go on, but nothing can be withdrawn.
+    Frozen,
+    /// After a chargeback, until an operator unlocks the account.
+    Locked,
+    /// Closed by an operator, for good.
+    Closed,
+}
+
+impl AccountStatus {
+    /// The name of the status as it appears in the output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        }
+    }
+
+    /// Whether an account in this status accepts transactions of the given type.
+    pub fn permits(self, transaction_type: TransactionType) -> bool {
+        match self {
+            AccountStatus::Active => true,
+            AccountStatus::Frozen => transaction_type != TransactionType::Withdrawal,
+            AccountStatus::Locked => transaction_type.is_admin(),
+            AccountStatus::Closed => false,
+        }
+    }
+
+    /// The error rejecting a transaction this status does not permit.
+    pub(crate) fn rejection(self, client_id: u16) -> Error {
+        match self {
+            AccountStatus::Frozen => Error::AccountFrozen(client_id),
+            AccountStatus::Closed => Error::AccountClosed(client_id),
+            // An active account permits every transaction
+            AccountStatus::Locked | AccountStatus::Active => Error::AccountLocked(client_id),
+        }
+    }
+}
+
+/// Why an account is not active.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusReason {
+    /// A disputed transaction was charged back.
+    Chargeback,
+    /// A transaction was charged back without a dispute.
+    UndisputedChargeback,
+    /// The partner sent a chargeback for a transaction the engine does not know.
+    UnknownChargeback,
+    /// An operator froze or closed the account, for the given reason.
+    Operator(String),
+}
+
+impl fmt::Display for StatusReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusReason::Chargeback => f.write_str("chargeback"),
+            StatusReason::UndisputedChargeback => f.write_str("undisputed_chargeback"),
+            StatusReason::UnknownChargeback => f.write_str("unknown_chargeback"),
+            StatusReason::Operator(reason) => f.write_str(reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permits() {
+        use TransactionType::*;
+
+        let types = [
+            Deposit, Withdrawal, Dispute, Resolve, Chargeback, Unlock, Freeze, Close, Adjustment,
+        ];
+        // Whether each status permits the types above, in the same order
+        let table = [
+            (AccountStatus::Active, [true; 9], None),
+            (
+                AccountStatus::Frozen,
+                [true, false, true, true, true, true, true, true, true],
+                Some("account_frozen"),
+            ),
+            (
+                AccountStatus::Locked,
+                [false, false, false, false, false, true, true, true, true],
+                Some("account_locked"),
+            ),
+            (AccountStatus::Closed, [false; 9], Some("account_closed")),
+        ];
+
+        for (status, permitted, code) in table {
+            for (transaction_type, permitted) in types.into_iter().zip(permitted) {
+                assert_eq!(
+                    status.permits(transaction_type),
+                    permitted,
+                    "{status:?} {}",
+                    transaction_type.as_str()
+                );
+            }
+            if let Some(code) = code {
+                assert_eq!(status.rejection(1).code(), code);
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use crate::engine::{account::Account, config::AccountStorage};
+
+/// Storage for the accounts of an [`Engine`](crate::Engine), keyed by client ID.
+///
+/// Implementations iterate in client ID order, which keeps the output deterministic.
+pub(crate) trait AccountStore: Send {
+    fn get(&self, client_id: u16) -> Option<&Account>;
+
+    /// Inserts or replaces the account of a client.
+    fn insert(&mut self, client_id: u16, account: Account);
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_>;
+
+    fn contains(&self, client_id: u16) -> bool {
+        self.get(client_id).is_some()
+    }
+}
+
+/// Creates an empty store of the configured kind.
+pub(crate) fn new_store(storage: AccountStorage) -> Box<dyn AccountStore> {
+    match storage {
+        AccountStorage::Dense => Box::new(DenseAccountStore::new()),
+        AccountStorage::Map => Box::new(MapAccountStore::default()),
+    }
+}
+
+/// One pre-allocated slot per possible client ID, so lookups are a plain index.
+struct DenseAccountStore {
+    slots: Vec<Option<Account>>,
+}
+
+impl DenseAccountStore {
+    fn new() -> Self {
+        let mut slots = Vec::new();
+        slots.resize_with(usize::from(u16::MAX) + 1, || None);
+        DenseAccountStore { slots }
+    }
+}
+
+impl AccountStore for DenseAccountStore {
+    fn get(&self, client_id: u16) -> Option<&Account> {
+        self.slots[usize::from(client_id)].as_ref()
+    }
+
+    fn insert(&mut self, client_id: u16, account: Account) {
+        self.slots[usize::from(client_id)] = Some(account);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_> {
+        Box::new(
+            (0..=u16::MAX)
+                .zip(&self.slots)
+                .filter_map(|(client_id, slot)| slot.as_ref().map(|account| (client_id, account))),
+        )
+    }
+}
+
+/// Only allocates for the clients seen, at the cost of a tree lookup per transaction. The tree
+/// keeps the clients in order for [`AccountStore::iter`].
+#[derive(Default)]
+struct MapAccountStore {
+    account_map: BTreeMap<u16, Account>,
+}
+
+impl AccountStore for MapAccountStore {
+    fn get(&self, client_id: u16) -> Option<&Account> {
+        self.account_map.get(&client_id)
+    }
+
+    fn insert(&mut self, client_id: u16, account: Account) {
+        self.account_map.insert(client_id, account);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (u16, &Account)> + '_> {
+        Box::new(
+            self.account_map
+                .iter()
+                .map(|(&client_id, account)| (client_id, account)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::AccountStatus;
+
+    #[test]
+    fn test_account_stores() {
+        for storage in [AccountStorage::Dense, AccountStorage::Map] {
+            let mut store = new_store(storage);
+            for client_id in [u16::MAX, 7, 0] {
+                store.insert(client_id, Account::new());
+            }
+            store.insert(
+                7,
+                Account::restore(7, &[], AccountStatus::Locked, None).unwrap(),
+            );
+
+            assert!(store.contains(0));
+            assert!(!store.contains(1));
+            assert!(store.get(7).unwrap().locked());
+            let client_ids: Vec<_> = store.iter().map(|(client_id, _)| client_id).collect();
+            assert_eq!(client_ids, [0, 7, u16::MAX]);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use rust_decimal::Decimal;
 
 use crate::engine::*;
@@ -25,47 +27,54 @@ pub fn get_client_output_map(engine: &Engine) -> HashMap<u16, EngineOutputItem>
 }
 
 pub fn deposit(client_id: u16, tx: u32, amount: &str) -> EngineTransaction {
-    EngineTransaction {
-        client_id,
-        op: Op::Deposit(Deposit {
-            transaction_id: tx,
-            amount: decimal(amount),
-        }),
-    }
+    EngineTransaction::deposit(client_id, tx, decimal(amount)).unwrap()
 }
 
-pub fn withdrawal(client_id: u16, amount: &str) -> EngineTransaction {
-    EngineTransaction {
-        client_id,
-        op: Op::Withdrawal(Withdrawal {
-            amount: decimal(amount),
-        }),
-    }
+pub fn withdrawal(client_id: u16, tx: u32, amount: &str) -> EngineTransaction {
+    EngineTransaction::withdrawal(client_id, tx, decimal(amount)).unwrap()
 }
 
 pub fn dispute(client_id: u16, tx: u32) -> EngineTransaction {
-    EngineTransaction {
-        client_id,
-        op: Op::Dispute(Dispute {
-            original_transaction_id: tx,
-        }),
-    }
+    EngineTransaction::dispute(client_id, tx)
 }
 
 pub fn resolve(client_id: u16, tx: u32) -> EngineTransaction {
-    EngineTransaction {
-        client_id,
-        op: Op::Resolve(Resolve {
-            original_transaction_id: tx,
-        }),
-    }
+    EngineTransaction::resolve(client_id, tx)
 }
 
 pub fn chargeback(client_id: u16, tx: u32) -> EngineTransaction {
-    EngineTransaction {
-        client_id,
-        op: Op::Chargeback(Chargeback {
-            original_transaction_id: tx,
-        }),
-    }
+    EngineTransaction::chargeback(client_id, tx)
+}
+
+pub fn unlock(client_id: u16, action_id: u32) -> EngineTransaction {
+    EngineTransaction::unlock(client_id, action_id, "reviewed").unwrap()
+}
+
+pub fn freeze(client_id: u16, action_id: u32) -> EngineTransaction {
+    EngineTransaction::freeze(client_id, action_id, "under review").unwrap()
+}
+
+pub fn close(client_id: u16, action_id: u32) -> EngineTransaction {
+    EngineTransaction::close(client_id, action_id, "closed by the client").unwrap()
+}
+
+pub fn adjustment(client_id: u16, action_id: u32, amount: &str) -> EngineTransaction {
+    EngineTransaction::adjustment(client_id, action_id, decimal(amount), "correction").unwrap()
+}
+
+pub fn partial_dispute(client_id: u16, tx: u32, amount: &str) -> EngineTransaction {
+    EngineTransaction::partial_dispute(client_id, tx, decimal(amount)).unwrap()
+}
+
+pub fn partial_resolve(client_id: u16, tx: u32, amount: &str) -> EngineTransaction {
+    EngineTransaction::partial_resolve(client_id, tx, decimal(amount)).unwrap()
+}
+
+pub fn partial_chargeback(client_id: u16, tx: u32, amount: &str) -> EngineTransaction {
+    EngineTransaction::partial_chargeback(client_id, tx, decimal(amount)).unwrap()
+}
+
+/// The credits less the debits of a ledger account.
+pub fn ledger_balance(engine: &Engine, account: LedgerAccount) -> CheckedDecimal {
+    engine.ledger().balance(account).credit_balance().unwrap()
 }
@@ -0,0 +1,75 @@
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// The time of a transaction, in milliseconds since the Unix epoch (UTC).
+///
+/// Serialized as the number of milliseconds, and displayed in RFC 3339 format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_millis(millis: i64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub fn as_millis(self) -> i64 {
+        self.0
+    }
+
+    /// Parses an RFC 3339 date and time (e.g. `2024-03-01T12:30:00Z`, with any offset), or an
+    /// integer number of milliseconds since the epoch.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidTimestamp(value.to_string());
+        if value.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+            return value.parse().map(Timestamp).map_err(|_| invalid());
+        }
+        DateTime::parse_from_rfc3339(value)
+            .map(|time| Timestamp(time.timestamp_millis()))
+            .map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match DateTime::from_timestamp_millis(self.0) {
+            Some(time) => f.write_str(&time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            // Outside of the range of dates chrono supports
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = Timestamp::from_millis(1_709_296_200_000);
+        assert_eq!(Timestamp::parse("2024-03-01T12:30:00Z").unwrap(), expected);
+        assert_eq!(
+            Timestamp::parse("2024-03-01T13:30:00+01:00").unwrap(),
+            expected
+        );
+        assert_eq!(Timestamp::parse("1709296200000").unwrap(), expected);
+        assert_eq!(expected.to_string(), "2024-03-01T12:30:00Z");
+        assert_eq!(
+            Timestamp::parse("2024-03-01T12:30:00.250Z")
+                .unwrap()
+                .to_string(),
+            "2024-03-01T12:30:00.250Z"
+        );
+
+        for invalid in ["", "yesterday", "2024-03-01", "1-2", "99999999999999999999"] {
+            assert!(matches!(
+                Timestamp::parse(invalid),
+                Err(Error::InvalidTimestamp(value)) if value == invalid
+            ));
+        }
+    }
+}
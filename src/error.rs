@@ -1,14 +1,22 @@
+use std::fmt;
+
+/// Errors produced while reading, validating or processing transactions.
+///
+/// Besides the `Display` message, every variant has a [code](Error::code), a
+/// [numeric code](Error::numeric_code) and a [category](Error::category) which are stable
+/// across releases and can be relied upon by other systems.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Invalid transaction amount: {0}")]
     InvalidTransactionAmount(&'static str),
     #[error("Invalid total amount: {source}")]
-    InvalidTotalAmount {
-        source: Box<Error>,
-    },
+    InvalidTotalAmount { source: Box<Error> },
     #[error("Duplicate transaction ID: {0}")]
     DuplicateTransactionId(u32),
-    #[error("Transaction found: {0}")]
+    #[error("Transaction ID belongs to another client: {0}")]
+    TransactionOwnedByAnotherClient(u32),
+    #[error("Transaction not found: {0}")]
     TransactionNotFound(u32),
     #[error("Insufficient funds for transaction")]
     InsufficientFunds,
@@ -19,23 +27,272 @@ pub enum Error {
     #[error("Dispute not allowed for transaction ID: {0}")]
     DisputeNotAllowed(u32),
     #[error("Dispute already charged back for transaction ID: {0}")]
-    DispputeAlreadyChargedback(u32),
+    DisputeAlreadyChargedBack(u32),
     #[error("Insufficient holds to resolve dispute")]
     InsufficientHoldsToResolveDispute,
     #[error("Account is locked: {0}")]
     AccountLocked(u16),
+    #[error("Line longer than {0} bytes")]
+    LineTooLong(usize),
+    #[error("Account not found: {0}")]
+    AccountNotFound(u16),
+    #[error("Dispute window expired for transaction ID: {0}")]
+    DisputeWindowExpired(u32),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("Timestamp earlier than a previous transaction of client: {0}")]
+    TimestampOutOfOrder(u16),
+    #[error("Unexpected CSV column, expected timestamp and then reason: {0}")]
+    UnexpectedCsvColumn(String),
+    #[error(
+        "Amount exceeds what can be disputed, resolved or charged back for transaction ID: {0}"
+    )]
+    DisputeAmountExceeded(u32),
+    #[error("A reason is required for admin operations")]
+    ReasonRequired,
+    #[error("Expected {expected} CSV fields, found {found}")]
+    CsvFieldCount { expected: usize, found: usize },
+    #[error("Admin operations are not allowed")]
+    AdminOperationsDisabled,
+    #[error("Account is frozen: {0}")]
+    AccountFrozen(u16),
+    #[error("Account is closed: {0}")]
+    AccountClosed(u16),
+    #[error("Account still holds funds: {0}")]
+    AccountNotEmpty(u16),
     #[error("Decimal parse error: {0}")]
     ParseDecimal(rust_decimal::Error),
     #[error("Unable to read CSV record: {0}")]
     ReadCsvRecord(csv::Error),
+    #[error("Invalid CSV record: {0}")]
+    InvalidCsvRecord(csv::Error),
     #[error("Unable to deserialize CSV record: {0}")]
     DeserializeCsvRecord(csv::Error),
+    #[error("Unable to deserialize JSON record: {0}")]
+    DeserializeJsonRecord(serde_json::Error),
     #[error("Decimal overflow during operation")]
     DecimalOverflow,
     #[error("Decimal underflow during operation")]
     DecimalUnderflow,
+    #[error("Ledger debits do not equal credits")]
+    LedgerOutOfBalance,
+    #[error("Ledger does not match the balance of client: {0}")]
+    LedgerMismatch(u16),
     #[error("Unable to write CSV record: {0}")]
     WriteCsvRecord(csv::Error),
+    #[error("Unable to write JSON record: {0}")]
+    WriteJsonRecord(serde_json::Error),
+    #[error("Unable to read journal entry at line {line}: {source}")]
+    ReadJournal {
+        line: u64,
+        source: serde_json::Error,
+    },
+    #[error("Unable to write journal entry: {0}")]
+    WriteJournal(serde_json::Error),
+    #[error("Unable to read snapshot: {0}")]
+    ReadSnapshot(serde_json::Error),
+    #[error("Unable to write snapshot: {0}")]
+    WriteSnapshot(serde_json::Error),
+    #[error("Unsupported snapshot version: {0}")]
+    UnsupportedSnapshotVersion(u32),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
-}
\ No newline at end of file
+    #[error(
+        "A journaled change could not be completed, the engine must be recovered from the journal"
+    )]
+    EngineFailed,
+    #[error("Unable to record audit event: {0}")]
+    WriteAudit(Box<Error>),
+}
+
+/// The broad class of an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+    /// The input is malformed or has invalid values.
+    Input,
+    /// The transaction is well-formed but not allowed in the current state.
+    BusinessRule,
+    /// The transaction would overflow the supported amount range, or the amounts do not add up.
+    Arithmetic,
+    /// Reading or writing a file or stream failed.
+    Io,
+}
+
+impl ErrorCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCategory::Input => "input",
+            ErrorCategory::BusinessRule => "business_rule",
+            ErrorCategory::Arithmetic => "arithmetic",
+            ErrorCategory::Io => "io",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Error {
+    /// A short identifier of the error kind, e.g. `insufficient_funds`.
+    pub fn code(&self) -> &'static str {
+        self.descriptor().0
+    }
+
+    /// A numeric identifier of the error kind. The thousands digit is the category:
+    /// 1 for input, 2 for business rules, 3 for arithmetic and 4 for I/O errors.
+    pub fn numeric_code(&self) -> u16 {
+        self.descriptor().1
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        self.descriptor().2
+    }
+
+    /// Wraps an error reading CSV input: only I/O failures are I/O errors, a malformed row
+    /// (e.g. invalid UTF-8) is an input error that rejects that row.
+    pub(crate) fn read_csv(e: csv::Error) -> Error {
+        if e.is_io_error() {
+            Error::ReadCsvRecord(e)
+        } else {
+            Error::InvalidCsvRecord(e)
+        }
+    }
+
+    // Codes must never be changed or reused once released, new variants get new codes.
+    fn descriptor(&self) -> (&'static str, u16, ErrorCategory) {
+        use ErrorCategory::*;
+
+        match self {
+            Error::InvalidTransactionAmount(_) => ("invalid_transaction_amount", 1001, Input),
+            Error::ParseDecimal(_) => ("parse_decimal", 1002, Input),
+            Error::DeserializeCsvRecord(_) => ("deserialize_csv_record", 1003, Input),
+            Error::LineTooLong(_) => ("line_too_long", 1004, Input),
+            Error::DeserializeJsonRecord(_) => ("deserialize_json_record", 1005, Input),
+            Error::InvalidTimestamp(_) => ("invalid_timestamp", 1006, Input),
+            Error::UnexpectedCsvColumn(_) => ("unexpected_csv_column", 1007, Input),
+            Error::ReasonRequired => ("reason_required", 1008, Input),
+            Error::CsvFieldCount { .. } => ("csv_field_count", 1009, Input),
+            Error::InvalidCsvRecord(_) => ("invalid_csv_record", 1010, Input),
+            Error::UnsupportedSnapshotVersion(_) => ("unsupported_snapshot_version", 1011, Input),
+            Error::DuplicateTransactionId(_) => ("duplicate_transaction_id", 2001, BusinessRule),
+            Error::TransactionOwnedByAnotherClient(_) => {
+                ("transaction_owned_by_another_client", 2002, BusinessRule)
+            }
+            Error::TransactionNotFound(_) => ("transaction_not_found", 2003, BusinessRule),
+            Error::InsufficientFunds => ("insufficient_funds", 2004, BusinessRule),
+            Error::DisputeAlreadyStarted(_) => ("dispute_already_started", 2005, BusinessRule),
+            Error::DisputeNotStarted(_) => ("dispute_not_started", 2006, BusinessRule),
+            Error::DisputeNotAllowed(_) => ("dispute_not_allowed", 2007, BusinessRule),
+            Error::DisputeAlreadyChargedBack(_) => {
+                ("dispute_already_charged_back", 2008, BusinessRule)
+            }
+            Error::InsufficientHoldsToResolveDispute => {
+                ("insufficient_holds_to_resolve_dispute", 2009, BusinessRule)
+            }
+            Error::AccountLocked(_) => ("account_locked", 2010, BusinessRule),
+            Error::AccountNotFound(_) => ("account_not_found", 2011, BusinessRule),
+            Error::DisputeWindowExpired(_) => ("dispute_window_expired", 2012, BusinessRule),
+            Error::TimestampOutOfOrder(_) => ("timestamp_out_of_order", 2013, BusinessRule),
+            Error::DisputeAmountExceeded(_) => ("dispute_amount_exceeded", 2014, BusinessRule),
+            Error::AdminOperationsDisabled => ("admin_operations_disabled", 2015, BusinessRule),
+            Error::AccountFrozen(_) => ("account_frozen", 2016, BusinessRule),
+            Error::AccountClosed(_) => ("account_closed", 2017, BusinessRule),
+            Error::AccountNotEmpty(_) => ("account_not_empty", 2018, BusinessRule),
+            Error::InvalidTotalAmount { .. } => ("invalid_total_amount", 3001, Arithmetic),
+            Error::DecimalOverflow => ("decimal_overflow", 3002, Arithmetic),
+            Error::DecimalUnderflow => ("decimal_underflow", 3003, Arithmetic),
+            Error::LedgerOutOfBalance => ("ledger_out_of_balance", 3004, Arithmetic),
+            Error::LedgerMismatch(_) => ("ledger_mismatch", 3005, Arithmetic),
+            Error::ReadCsvRecord(_) => ("read_csv_record", 4001, Io),
+            Error::WriteCsvRecord(_) => ("write_csv_record", 4002, Io),
+            Error::ReadJournal { .. } => ("read_journal", 4003, Io),
+            Error::WriteJournal(_) => ("write_journal", 4004, Io),
+            Error::ReadSnapshot(_) => ("read_snapshot", 4005, Io),
+            Error::WriteSnapshot(_) => ("write_snapshot", 4006, Io),
+            // 4007 was unsupported_snapshot_version, now an input error
+            Error::Io(_) => ("io", 4008, Io),
+            Error::WriteJsonRecord(_) => ("write_json_record", 4009, Io),
+            Error::EngineFailed => ("engine_failed", 4010, Io),
+            Error::WriteAudit(_) => ("write_audit", 4011, Io),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let errors = [
+            Error::InvalidTransactionAmount("Amount is required"),
+            Error::DuplicateTransactionId(1),
+            Error::TransactionOwnedByAnotherClient(1),
+            Error::TransactionNotFound(1),
+            Error::InsufficientFunds,
+            Error::DisputeAlreadyStarted(1),
+            Error::DisputeNotStarted(1),
+            Error::DisputeNotAllowed(1),
+            Error::DisputeAlreadyChargedBack(1),
+            Error::InsufficientHoldsToResolveDispute,
+            Error::AccountLocked(1),
+            Error::LineTooLong(1),
+            Error::AccountNotFound(1),
+            Error::DisputeWindowExpired(1),
+            Error::TimestampOutOfOrder(1),
+            Error::DisputeAmountExceeded(1),
+            Error::ReasonRequired,
+            Error::CsvFieldCount {
+                expected: 4,
+                found: 3,
+            },
+            Error::AdminOperationsDisabled,
+            Error::AccountFrozen(1),
+            Error::AccountClosed(1),
+            Error::AccountNotEmpty(1),
+            Error::InvalidTimestamp("yesterday".to_string()),
+            Error::UnexpectedCsvColumn("note".to_string()),
+            Error::DecimalOverflow,
+            Error::DecimalUnderflow,
+            Error::LedgerOutOfBalance,
+            Error::LedgerMismatch(1),
+            Error::UnsupportedSnapshotVersion(2),
+            Error::EngineFailed,
+            Error::WriteAudit(Box::new(Error::DecimalOverflow)),
+        ];
+
+        let codes: HashSet<_> = errors.iter().map(Error::code).collect();
+        let numeric_codes: HashSet<_> = errors.iter().map(Error::numeric_code).collect();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(numeric_codes.len(), errors.len());
+
+        for error in &errors {
+            let category_digit = match error.category() {
+                ErrorCategory::Input => 1,
+                ErrorCategory::BusinessRule => 2,
+                ErrorCategory::Arithmetic => 3,
+                ErrorCategory::Io => 4,
+            };
+            assert_eq!(error.numeric_code() / 1000, category_digit);
+        }
+
+        // Released codes must not change
+        assert_eq!(Error::InsufficientFunds.code(), "insufficient_funds");
+        assert_eq!(Error::InsufficientFunds.numeric_code(), 2004);
+        assert_eq!(Error::DecimalOverflow.category(), ErrorCategory::Arithmetic);
+        assert_eq!(
+            Error::DisputeAlreadyChargedBack(1).code(),
+            "dispute_already_charged_back"
+        );
+        assert_eq!(
+            Error::TransactionNotFound(7).to_string(),
+            "Transaction not found: 7"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+use crate::{
+    engine::{Engine, EngineOutputItem, EngineTransaction, TransactionSummary, parse_json_record},
+    error::{Error, ErrorCategory},
+};
+
+/// The number of accounts returned by `GET /accounts` when no `limit` is given.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// The largest `limit` accepted by `GET /accounts`.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Builds the HTTP API for `engine`:
+/// - `POST /transactions` applies a JSON transaction in the JSON Lines input format, e.g.
+///   `{"type": "deposit", "client": 1, "tx": 1, "amount": "10.5"}`.
+/// - `GET /accounts/{client}` returns an account summary with the client's deposits and
+///   withdrawals and their dispute status.
+/// - `GET /accounts?after=<client>&limit=<n>` lists account summaries by client ID.
+///
+/// Rejections are answered with the [`Error`] code, numeric code, category and message, and a
+/// status code derived from the category.
+pub fn http_router(engine: Arc<Mutex<Engine>>) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .with_state(engine)
+}
+
+/// Serves [`http_router`] on `listener` until `shutdown` completes.
+pub async fn serve_http(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), Error> {
+    axum::serve(listener, http_router(engine))
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+fn lock(engine: &Mutex<Engine>) -> MutexGuard<'_, Engine> {
+    engine.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs `f` with the locked engine on a blocking thread, so that waiting for the lock and the
+/// journal and record store I/O do not stall the runtime.
+async fn with_engine<T: Send + 'static>(
+    engine: Arc<Mutex<Engine>>,
+    f: impl FnOnce(&mut Engine) -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    tokio::task::spawn_blocking(move || f(&mut lock(&engine)))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+#[derive(Serialize)]
+struct TransactionResponse {
+    status: &'static str,
+    client: u16,
+    tx: u32,
+}
+
+async fn submit_transaction(
+    State(engine): State<Arc<Mutex<Engine>>>,
+    body: Bytes,
+) -> Result<Json<TransactionResponse>, ErrorResponse> {
+    // Invalid UTF-8 is replaced, and then rejected by the JSON or amount parser
+    let body = String::from_utf8_lossy(&body);
+    let record = parse_json_record(&body)?;
+    let transaction = EngineTransaction::parse_csv_record(&record)?;
+    with_engine(engine, |engine| engine.process_transaction(transaction)).await?;
+
+    Ok(Json(TransactionResponse {
+        status: "accepted",
+        client: record.client,
+        tx: record.tx,
+    }))
+}
+
+#[derive(Serialize)]
+struct AccountResponse {
+    #[serde(flatten)]
+    account: EngineOutputItem,
+    transactions: Vec<TransactionSummary>,
+}
+
+async fn get_account(
+    State(engine): State<Arc<Mutex<Engine>>>,
+    Path(client): Path<u16>,
+) -> Result<Json<AccountResponse>, ErrorResponse> {
+    let (account, mut transactions) = with_engine(engine, move |engine| {
+        let account = engine
+            .output_item(client)
+            .ok_or(Error::AccountNotFound(client))?;
+        Ok((account, engine.client_transactions(client)?))
+    })
+    .await?;
+    transactions.sort_by_key(|transaction| transaction.tx);
+
+    Ok(Json(AccountResponse {
+        account,
+        transactions,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+    /// Only list clients with a greater ID.
+    after: Option<u16>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct AccountListResponse {
+    accounts: Vec<EngineOutputItem>,
+    /// The `after` parameter for the next page, if there is one.
+    next: Option<u16>,
+}
+
+async fn list_accounts(
+    State(engine): State<Arc<Mutex<Engine>>>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<AccountListResponse>, ErrorResponse> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    // Accounts are listed in client ID order, so a page ends where the next one starts
+    let mut accounts: Vec<_> = with_engine(engine, move |engine| {
+        Ok(engine
+            .output_items()
+            .skip_while(|item| params.after.is_some_and(|after| item.client <= after))
+            .take(limit + 1)
+            .collect())
+    })
+    .await?;
+
+    let next = if accounts.len() > limit {
+        accounts.truncate(limit);
+        accounts.last().map(|item| item.client)
+    } else {
+        None
+    };
+    Ok(Json(AccountListResponse { accounts, next }))
+}
+
+struct ErrorResponse(Error);
+
+impl From<Error> for ErrorResponse {
+    fn from(error: Error) -> Self {
+        ErrorResponse(error)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    numeric_code: u16,
+    category: &'static str,
+    message: String,
+}
+
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        let status = match (&self.0, self.0.category()) {
+            (Error::AccountNotFound(_), _) => StatusCode::NOT_FOUND,
+            (_, ErrorCategory::Input) => StatusCode::BAD_REQUEST,
+            (_, ErrorCategory::BusinessRule | ErrorCategory::Arithmetic) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            (_, ErrorCategory::Io) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let body = ErrorBody {
+            code: self.0.code(),
+            numeric_code: self.0.numeric_code(),
+            category: self.0.category().as_str(),
+            message: self.0.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{ClientTransactions, EngineConfig};
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn start() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let engine = Engine::with_config(EngineConfig {
+            client_transactions: ClientTransactions::Indexed,
+            ..Default::default()
+        });
+        let engine = Arc::new(Mutex::new(engine));
+        tokio::spawn(serve_http(listener, engine, std::future::pending()));
+        addr
+    }
+
+    async fn request(
+        addr: std::net::SocketAddr,
+        method: &str,
+        path: &str,
+        body: Option<Value>,
+    ) -> (u16, Value) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    async fn post(addr: std::net::SocketAddr, transaction: Value) -> (u16, Value) {
+        request(addr, "POST", "/transactions", Some(transaction)).await
+    }
+
+    #[tokio::test]
+    async fn test_http_transactions_and_account() {
+        let addr = start().await;
+
+        let (status, body) = post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 1, "amount": "10.0"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"status": "accepted", "client": 1, "tx": 1}));
+        post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 2, "amount": "5.0"}),
+        )
+        .await;
+        let (status, _) = post(addr, json!({"type": "dispute", "client": 1, "tx": 2})).await;
+        assert_eq!(status, 200);
+
+        let (status, body) = post(
+            addr,
+            json!({"type": "withdrawal", "client": 1, "tx": 3, "amount": "20.0"}),
+        )
+        .await;
+        assert_eq!(status, 422);
+        assert_eq!(body["code"], "insufficient_funds");
+        assert_eq!(body["numeric_code"], 2004);
+        assert_eq!(body["category"], "business_rule");
+
+        let (status, body) = post(
+            addr,
+            json!({"type": "deposit", "client": 1, "tx": 4, "amount": "-1"}),
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "invalid_transaction_amount");
+        let (status, body) = post(addr, json!({"type": "unknown"})).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "deserialize_json_record");
+
+        let (status, body) = request(addr, "GET", "/accounts/1", None).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            json!({
+                "client": 1,
+                "available": "10.0",
+                "held": "5.0",
+                "total": "15.0",
+                "locked": false,
+                "status": "active",
+                "reason": null,
+                "transactions": [
+                    {
+                        "tx": 1,
+                        "kind": "deposit",
+                        "amount": "10.0",
+                        "dispute_status": "not_started",
+                        "disputed": "0",
+                        "charged_back": "0",
+                    },
+                    {
+                        "tx": 2,
+                        "kind": "deposit",
+                        "amount": "5.0",
+                        "dispute_status": "in_progress",
+                        "disputed": "5.0",
+                        "charged_back": "0",
+                    },
+                ],
+            })
+        );
+
+        let (status, body) = request(addr, "GET", "/accounts/2", None).await;
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "account_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_http_account_pages() {
+        let addr = start().await;
+        for client in 1..=5 {
+            post(
+                addr,
+                json!({"type": "deposit", "client": client, "tx": client, "amount": "1"}),
+            )
+            .await;
+        }
+
+        let (_, body) = request(addr, "GET", "/accounts?limit=2", None).await;
+        let clients: Vec<_> = body["accounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|account| account["client"].as_u64().unwrap())
+            .collect();
+        assert_eq!(clients, [1, 2]);
+        assert_eq!(body["next"], 2);
+
+        let (_, body) = request(addr, "GET", "/accounts?after=4&limit=2", None).await;
+        assert_eq!(body["accounts"].as_array().unwrap().len(), 1);
+        assert_eq!(body["accounts"][0]["client"], 5);
+        assert_eq!(body["next"], Value::Null);
+    }
+}
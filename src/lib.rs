@@ -0,0 +1,47 @@
+//! A toy payment engine.
+//!
+//! The engine keeps one account per client and applies deposits, withdrawals, disputes,
+//! resolves and chargebacks to them. Transactions can be built directly with the
+//! [`EngineTransaction`] constructors or parsed from CSV records read by [`CsvReader`].
+//!
+//! ```
+//! use coding_test::{CheckedDecimal, Engine, EngineTransaction};
+//!
+//! let mut engine = Engine::new();
+//! let amount = CheckedDecimal::parse("10.5").unwrap();
+//! engine
+//!     .process_transaction(EngineTransaction::deposit(1, 1, amount).unwrap())
+//!     .unwrap();
+//!
+//! let account = engine.account(1).unwrap();
+//! assert_eq!(account.balance().available, amount);
+//! assert!(!account.locked());
+//! ```
+//!
+//! Everything that is part of the public API is re-exported from the crate root; the
+//! module layout underneath is an implementation detail.
+
+mod compression;
+mod engine;
+mod error;
+mod http;
+mod server;
+
+pub use crate::compression::{CompressWriter, Compression};
+pub use crate::engine::{
+    Account, AccountBalance, AccountHistory, AccountStatus, AccountStorage, Adjustment,
+    AdminAction, AdminOperation, AdminOperations, AllowNegativeAvailablePolicy, AuditEvent,
+    AuditSink, Chargeback, CheckedDecimal, ClientTransactions, CsvInputRecord, CsvReader,
+    DefaultDisputePolicy, Deposit, DiskRecordStore, Dispute, DisputeContext, DisputePolicy,
+    DisputeStatus, DisputeWindow, Engine, EngineConfig, EngineOutputItem, EngineTransaction,
+    FileAuditSink, HistoryEntry, JournalSync, JsonLinesReader, Ledger, LedgerAccount,
+    LedgerBalance, LedgerEntries, LedgerEntry, MemoryAuditSink, MemoryRecordStore, Op,
+    OutOfOrderTimestamps, OutputFormat, OutputOrder, Posting, RecordStore, RejectWriter, Rejection,
+    Resolve, ShardedEngine, SingleDisputePolicy, StatementFormat, StatusReason, Timestamp,
+    TransactionKind, TransactionRecord, TransactionSource, TransactionSummary, TransactionType,
+    Transition, TrialBalance, TrialBalanceLine, Withdrawal, WithdrawalDisputes, write_output_items,
+    write_statement,
+};
+pub use crate::error::{Error, ErrorCategory};
+pub use crate::http::{http_router, serve_http};
+pub use crate::server::serve;
@@ -1,65 +1,888 @@
-use std::io::BufReader;
-use std::path::PathBuf;
-use std::fs::File;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
-mod engine;
-mod error;
+use coding_test::{
+    AccountHistory, AccountStorage, AdminOperations, AllowNegativeAvailablePolicy,
+    ClientTransactions, CompressWriter, Compression, CsvReader, DefaultDisputePolicy,
+    DiskRecordStore, DisputePolicy, DisputeWindow, Engine, EngineConfig, EngineTransaction, Error,
+    ErrorCategory, FileAuditSink, JournalSync, JsonLinesReader, LedgerEntries,
+    OutOfOrderTimestamps, OutputFormat, OutputOrder, RejectWriter, Rejection, ShardedEngine,
+    SingleDisputePolicy, StatementFormat, Timestamp, TransactionSource, WithdrawalDisputes,
+    serve_http, write_output_items, write_statement,
+};
 
-use crate::engine::{Engine, EngineTransaction, CsvReader};
-use crate::error::Error;
-
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
 
 /// A toy transaction processing engine
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Path to the CSV file to process
-    path: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Accept newline-delimited CSV transactions over TCP, until interrupted with Ctrl-C
+    Serve(ServeArgs),
+    /// Process the input files and print a running-balance statement of some clients
+    Statement(StatementArgs),
+}
+
+/// The input files and how they are read.
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// Input files, processed in order against the same engine. `-` reads from stdin
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Verbose mode
+    #[arg(short, long, default_value_t = false)]
+    verbose: bool,
+
+    /// Write every rejected row to this file as CSV, with an error code and message
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+
+    /// Format of the input files [default: from each file's extension, `jsonl` for `.jsonl`
+    /// and `.ndjson` files, `csv` otherwise and for stdin]
+    #[arg(long, value_enum)]
+    input_format: Option<InputFormatArg>,
+
+    /// Only apply the transactions up to this time (RFC 3339 or milliseconds since the epoch),
+    /// to report the balances as of then. Transactions without a timestamp are applied
+    #[arg(long, value_parser = parse_timestamp)]
+    as_of: Option<Timestamp>,
+
+    /// Number of worker threads; transactions are routed to workers by client ID
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), conflicts_with_all = ["journal", "state_in", "record_file", "dispute_window", "audit_log"])]
+    threads: u16,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatementArgs {
+    /// Client to print the statement of; can be given several times
+    #[arg(long = "client", required = true)]
+    clients: Vec<u16>,
+
+    /// Format of the statement written to stdout
+    #[arg(long, value_enum, default_value_t = StatementFormatArg::Text)]
+    format: StatementFormatArg,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    listen: SocketAddr,
+
+    /// Also serve the HTTP/JSON API on this address
+    #[arg(long)]
+    http: Option<SocketAddr>,
 
     /// Verbose mode
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+/// Options shared by every mode: the engine configuration and its persistent state.
+#[derive(clap::Args, Debug)]
+struct EngineArgs {
+    /// Allow disputes, resolves and chargebacks against withdrawals
+    #[arg(long, default_value_t = false)]
+    withdrawal_disputes: bool,
+
+    /// Journal file recording every state change. If it already exists, the engine state is
+    /// recovered from it and the transactions it covers are skipped
+    #[arg(long)]
+    journal: Option<PathBuf>,
+
+    /// Sync every journal entry to the disk before applying it, so that the journal survives a
+    /// power loss. Slower, as every transaction waits for the disk
+    #[arg(long, default_value_t = false, requires = "journal")]
+    journal_sync: bool,
+
+    /// Snapshot to load the engine state from before processing
+    #[arg(long)]
+    state_in: Option<PathBuf>,
+
+    /// File to save a snapshot of the engine state to after processing
+    #[arg(long)]
+    state_out: Option<PathBuf>,
+
+    /// Rules applied to disputes, resolves and chargebacks
+    #[arg(long, value_enum, default_value_t = DisputePolicyArg::Default)]
+    dispute_policy: DisputePolicyArg,
+
+    /// How long a deposit or withdrawal can be disputed, after which its record is evicted: a
+    /// number of subsequent transactions, or a time with an `s`, `m`, `h` or `d` suffix
+    /// [default: no limit]
+    #[arg(long, value_parser = parse_dispute_window)]
+    dispute_window: Option<DisputeWindow>,
+
+    /// Reject transactions with a timestamp earlier than a previous transaction of the client
+    #[arg(long, default_value_t = false)]
+    reject_out_of_order: bool,
+
+    /// Accept the admin operations (unlock, freeze, close and adjustment). Only give it for
+    /// input coming from operators
+    #[arg(long, default_value_t = false)]
+    admin_operations: bool,
+
+    /// File to write the admin operations accepted to after processing, as JSON Lines
+    #[arg(long)]
+    admin_log: Option<PathBuf>,
+
+    /// File to append an audit event to for every accepted transaction, with the balance before
+    /// and after and the dispute status and account status changes, as JSON Lines
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// File to write every ledger posting made by this run to after processing, as CSV
+    #[arg(long)]
+    ledger: Option<PathBuf>,
+
+    /// File to write the trial balance of the ledger to after processing, as CSV. Fails if the
+    /// debits and credits differ or the ledger does not match the account balances
+    #[arg(long)]
+    trial_balance: Option<PathBuf>,
+
+    /// Data structure holding the accounts
+    #[arg(long, value_enum, default_value_t = AccountStorageArg::Dense)]
+    account_storage: AccountStorageArg,
+
+    /// Format of the account summaries written to stdout
+    #[arg(long, value_enum, default_value_t = OutputFormatArg::Csv)]
+    output_format: OutputFormatArg,
+
+    /// Order of the account summaries written to stdout
+    #[arg(long, value_enum, default_value_t = SortArg::Client)]
+    sort: SortArg,
+
+    /// Compress the account summaries written to stdout
+    #[arg(long, value_enum, default_value_t = CompressionArg::None)]
+    output_compress: CompressionArg,
+
+    /// Keep the transaction records in this scratch file instead of in memory, to bound the
+    /// memory used on large inputs. The file is deleted on exit
+    #[arg(long)]
+    record_file: Option<PathBuf>,
+
+    /// Memory used to cache the records of `--record-file`, in bytes or with a K, M or G suffix
+    #[arg(long, default_value = "256M", value_parser = parse_size, requires = "record_file")]
+    record_cache: usize,
+
+    /// Keep the history of every account, for statements
+    #[arg(skip)]
+    account_history: bool,
+
+    /// Index the transaction IDs of every client, for the HTTP account summaries
+    #[arg(skip)]
+    client_index: bool,
+}
+
+impl EngineArgs {
+    fn config(&self) -> EngineConfig {
+        let mut config = EngineConfig::default();
+        if self.withdrawal_disputes {
+            config.withdrawal_disputes = WithdrawalDisputes::Allowed;
+        }
+        config.dispute_policy = self.dispute_policy.policy();
+        config.dispute_window = self.dispute_window;
+        if self.reject_out_of_order {
+            config.out_of_order_timestamps = OutOfOrderTimestamps::Rejected;
+        }
+        if self.admin_operations {
+            config.admin_operations = AdminOperations::Allowed;
+        }
+        if self.ledger.is_some() {
+            config.ledger_entries = LedgerEntries::Kept;
+        }
+        if self.account_history {
+            config.account_history = AccountHistory::Kept;
+        }
+        if self.journal_sync {
+            config.journal_sync = JournalSync::EveryEntry;
+        }
+        if self.client_index {
+            config.client_transactions = ClientTransactions::Indexed;
+        }
+        config.account_storage = match self.account_storage {
+            AccountStorageArg::Dense => AccountStorage::Dense,
+            AccountStorageArg::Map => AccountStorage::Map,
+        };
+        config
+    }
+
+    /// Creates the engine, loading `--state-in`, switching to `--record-file`, opening
+    /// `--audit-log` and attaching `--journal` if given.
+    fn engine(&self) -> Result<Engine, Error> {
+        let mut engine = match &self.state_in {
+            Some(path) => {
+                Engine::load_snapshot_with_config(self.config(), BufReader::new(File::open(path)?))?
+            }
+            None => Engine::with_config(self.config()),
+        };
+        if let Some(path) = &self.record_file {
+            engine.set_record_store(Box::new(DiskRecordStore::create(path, self.record_cache)?))?;
+        }
+        // Opened before the journal, so that the replayed transactions missing from the audit log
+        // are audited
+        if let Some(path) = &self.audit_log {
+            engine.set_audit_sink(Box::new(FileAuditSink::open(path)?));
+        }
+        if let Some(path) = &self.journal {
+            engine.attach_journal(path)?;
+        }
+        Ok(engine)
+    }
+
+    /// Writes the accounts to stdout, then saves the engine state, see [`EngineArgs::save`].
+    fn finish(&self, engine: &Engine) -> Result<(), Error> {
+        let format = match self.output_format {
+            OutputFormatArg::Csv => OutputFormat::Csv,
+            OutputFormatArg::Jsonl => OutputFormat::JsonLines,
+            OutputFormatArg::Json => OutputFormat::Json,
+        };
+        let compression = self.output_compress.compression();
+        let mut w = CompressWriter::new(std::io::stdout().lock(), compression)?;
+        let order = match self.sort {
+            SortArg::Client => OutputOrder::Client,
+            SortArg::Total => OutputOrder::Total,
+            SortArg::Locked => OutputOrder::Locked,
+        };
+        write_output_items(&mut w, format, engine.sorted_output_items(order))?;
+        w.finish()?.flush()?;
+        self.save(engine)
+    }
+
+    /// Writes the snapshot to `--state-out`, the admin operations to `--admin-log`, the ledger
+    /// postings to `--ledger` and the trial balance to `--trial-balance` if given.
+    fn save(&self, engine: &Engine) -> Result<(), Error> {
+        if let Some(path) = &self.state_out {
+            let mut w = BufWriter::new(File::create(path)?);
+            engine.save_snapshot(&mut w)?;
+            w.flush()?;
+        }
+        if let Some(path) = &self.admin_log {
+            let mut w = BufWriter::new(File::create(path)?);
+            for action in engine.admin_actions() {
+                serde_json::to_writer(&mut w, action).map_err(Error::WriteJsonRecord)?;
+                w.write_all(b"\n")?;
+            }
+            w.flush()?;
+        }
+        if let Some(path) = &self.ledger {
+            let mut w = csv::Writer::from_writer(File::create(path)?);
+            for entry in engine.ledger().entries() {
+                w.serialize(entry).map_err(Error::WriteCsvRecord)?;
+            }
+            w.flush()?;
+        }
+        if let Some(path) = &self.trial_balance {
+            let trial_balance = engine.trial_balance()?;
+            let mut w = csv::Writer::from_writer(File::create(path)?);
+            for line in &trial_balance.lines {
+                w.serialize(line).map_err(Error::WriteCsvRecord)?;
+            }
+            w.write_record([
+                "total".to_string(),
+                trial_balance.total_debit.to_string(),
+                trial_balance.total_credit.to_string(),
+            ])
+            .map_err(Error::WriteCsvRecord)?;
+            w.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<Timestamp, String> {
+    Timestamp::parse(value).map_err(|e| e.to_string())
+}
+
+/// Parses a dispute window: a number of transactions, or a duration such as `90d`.
+fn parse_dispute_window(value: &str) -> Result<DisputeWindow, String> {
+    let invalid = || format!("invalid dispute window `{value}`");
+    let (digits, unit) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 60 * 60),
+        Some((i, 'd')) => (&value[..i], 24 * 60 * 60),
+        _ => {
+            let transactions = value.parse().map_err(|_| invalid())?;
+            return Ok(DisputeWindow::Transactions(transactions));
+        }
+    };
+    let seconds: u64 = digits.parse().map_err(|_| invalid())?;
+    seconds
+        .checked_mul(unit)
+        .map(|seconds| DisputeWindow::Duration(Duration::from_secs(seconds)))
+        .ok_or_else(|| format!("dispute window `{value}` is too long"))
+}
+
+/// Parses a size in bytes, with an optional binary K, M or G suffix.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    let size: usize = digits
+        .parse()
+        .map_err(|_| format!("invalid size `{value}`"))?;
+    size.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size `{value}` is too large"))
+}
+
+/// The engine processing the input: single-threaded, or sharded across worker threads.
+enum Processor {
+    Single(Box<Engine>),
+    Sharded(ShardedEngine),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum InputFormatArg {
+    /// CSV with a `type, client, tx, amount` header and optional `timestamp` and `reason` columns
+    Csv,
+    /// One JSON object per line, with the same fields as the CSV columns
+    Jsonl,
+}
+
+impl InputFormatArg {
+    fn detect(path: &Path) -> Self {
+        // The format of `drop.csv.gz` is given by the extension before the compression's
+        let path = match Compression::from_extension(path) {
+            Some(_) => Path::new(path.file_stem().unwrap_or_default()),
+            None => path,
+        };
+        match path.extension().and_then(OsStr::to_str) {
+            Some("jsonl" | "ndjson") => InputFormatArg::Jsonl,
+            _ => InputFormatArg::Csv,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormatArg {
+    /// CSV with a `client, available, held, total, locked, status, reason` header
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+    /// A single JSON array
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StatementFormatArg {
+    /// CSV with a row per transaction of every client
+    Csv,
+    /// A table per client, followed by its closing balance
+    Text,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum AccountStorageArg {
+    /// A pre-allocated slot for every client ID, fastest
+    Dense,
+    /// An ordered map of the clients seen, smallest for few clients
+    Map,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SortArg {
+    /// By client ID
+    Client,
+    /// By total funds, largest first
+    Total,
+    /// Locked accounts first
+    Locked,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CompressionArg {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl CompressionArg {
+    fn compression(self) -> Compression {
+        match self {
+            CompressionArg::None => Compression::None,
+            CompressionArg::Gzip => Compression::Gzip,
+            CompressionArg::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DisputePolicyArg {
+    /// Reject disputes exceeding the available funds, allow re-disputes, lock on undisputed chargebacks
+    Default,
+    /// Like `default`, but disputes may leave the available funds negative
+    AllowNegativeAvailable,
+    /// Like `default`, but a resolved dispute cannot be reopened
+    SingleDispute,
+}
+
+impl DisputePolicyArg {
+    fn policy(self) -> Arc<dyn DisputePolicy> {
+        match self {
+            DisputePolicyArg::Default => Arc::new(DefaultDisputePolicy),
+            DisputePolicyArg::AllowNegativeAvailable => Arc::new(AllowNegativeAvailablePolicy),
+            DisputePolicyArg::SingleDispute => Arc::new(SingleDisputePolicy),
+        }
+    }
 }
 
 fn main() -> Result<(), Error> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Serve(serve_args)) => serve(serve_args),
+        Some(Command::Statement(statement_args)) => statement(statement_args),
+        None => {
+            let engine = process(&args.input, &args.engine, &mut std::io::stdin().lock())?;
+            args.engine.finish(&engine)
+        }
+    }
+}
 
-    let r = File::open(args.path)?;
-    let r = BufReader::new(r);
-    let mut csv_reader = CsvReader::from_reader(r)?;
-    let mut engine = Engine::new();
-
-    let mut line_number = 1;
-    while let Some(record) = csv_reader.read_next()? {
-        line_number += 1;
-        let tx = match EngineTransaction::parse_csv_record(&record) {
-            Ok(tx) => tx,
-            Err(e) => {
+fn serve(mut args: ServeArgs) -> Result<(), Error> {
+    // Account summaries would otherwise scan every record, under the engine lock
+    args.engine.client_index = args.http.is_some();
+    let engine = Arc::new(Mutex::new(args.engine.engine()?));
+    let runtime = tokio::runtime::Runtime::new()?;
+    let served = runtime.block_on(async {
+        let listener = TcpListener::bind(args.listen).await?;
+        if args.verbose {
+            eprintln!("Listening on {}", listener.local_addr()?);
+        }
+        let http_listener = match args.http {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                if args.verbose {
+                    eprintln!("Serving the HTTP API on {}", listener.local_addr()?);
+                }
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let (stop, stopped) = watch::channel(());
+        tokio::spawn(async move {
+            // If the handler cannot be installed, the server runs until the process is killed
+            match tokio::signal::ctrl_c().await {
+                Ok(()) => {
+                    let _ = stop.send(());
+                }
+                Err(_) => std::future::pending().await,
+            }
+        });
+        let shutdown = move || {
+            let mut stopped = stopped.clone();
+            async move {
+                let _ = stopped.changed().await;
+            }
+        };
+
+        let http = async {
+            match http_listener {
+                Some(listener) => serve_http(listener, engine.clone(), shutdown()).await,
+                None => Ok(()),
+            }
+        };
+        tokio::try_join!(
+            coding_test::serve(listener, engine.clone(), shutdown()),
+            http
+        )
+        .map(|_| ())
+    });
+
+    // The transactions acknowledged before a server failed are still written out
+    let engine = engine.lock().unwrap_or_else(PoisonError::into_inner);
+    let finished = args.engine.finish(&engine);
+    served.and(finished)
+}
+
+/// The name of stdin in diagnostics.
+const STDIN_NAME: &str = "<stdin>";
+
+/// Sharded rejections are tagged with the input's index above this many bits, and the line
+/// number below them.
+const TAG_LINE_BITS: u32 = 40;
+
+fn input_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        STDIN_NAME.to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+fn open_source<'a>(
+    path: &Path,
+    format: Option<InputFormatArg>,
+    stdin: &'a mut dyn BufRead,
+) -> Result<Box<dyn TransactionSource + 'a>, Error> {
+    let mut r: Box<dyn BufRead + 'a> = if path == Path::new("-") {
+        Box::new(stdin)
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+    let compression = match Compression::from_extension(path) {
+        Some(compression) => compression,
+        None => Compression::detect(&mut r)?,
+    };
+    let r = compression.decoder(r)?;
+    Ok(
+        match format.unwrap_or_else(|| InputFormatArg::detect(path)) {
+            InputFormatArg::Csv => Box::new(CsvReader::from_reader(r)?),
+            InputFormatArg::Jsonl => Box::new(JsonLinesReader::from_reader(r)),
+        },
+    )
+}
+
+fn statement(mut args: StatementArgs) -> Result<(), Error> {
+    args.engine.account_history = true;
+    let engine = process(&args.input, &args.engine, &mut std::io::stdin().lock())?;
+    let format = match args.format {
+        StatementFormatArg::Csv => StatementFormat::Csv,
+        StatementFormatArg::Text => StatementFormat::Text,
+    };
+    let mut w = std::io::stdout().lock();
+    write_statement(&mut w, format, &engine, &args.clients)?;
+    w.flush()?;
+    args.engine.save(&engine)
+}
+
+/// Processes the input files and returns the engine holding the result.
+/// Processes the inputs of `args`, reading the one named `-` from `stdin`.
+fn process(
+    args: &InputArgs,
+    engine_args: &EngineArgs,
+    stdin: &mut dyn BufRead,
+) -> Result<Engine, Error> {
+    let names: Vec<_> = args.paths.iter().map(|path| input_name(path)).collect();
+    let mut processor = if args.threads > 1 {
+        Processor::Sharded(ShardedEngine::new(
+            engine_args.config(),
+            usize::from(args.threads),
+        ))
+    } else {
+        Processor::Single(Box::new(engine_args.engine()?))
+    };
+
+    // Transactions already recorded in the journal are skipped
+    let mut skip = match &processor {
+        Processor::Single(engine) => engine.transaction_count(),
+        Processor::Sharded(_) => 0,
+    };
+    if args.verbose && skip > 0 {
+        eprintln!(
+            "Resuming after {} transactions recovered from the journal",
+            skip
+        );
+    }
+
+    let mut after_cutoff = 0u64;
+    let mut rejects = match &args.rejects {
+        // A resumed run adds its rejections to the report of the interrupted run
+        Some(path) if skip > 0 => {
+            let file = OpenOptions::new().append(true).create(true).open(path)?;
+            let has_header = file.metadata()?.len() > 0;
+            let w = BufWriter::new(file);
+            Some(if has_header {
+                RejectWriter::appending(w)
+            } else {
+                RejectWriter::from_writer(w)
+            })
+        }
+        Some(path) => Some(RejectWriter::from_writer(BufWriter::new(File::create(
+            path,
+        )?))),
+        None => None,
+    };
+
+    for (index, (path, name)) in args.paths.iter().zip(&names).enumerate() {
+        let mut source = open_source(path, args.input_format, stdin)?;
+        loop {
+            let record = match source.read_next() {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(
+                    e @ (Error::DeserializeCsvRecord(_)
+                    | Error::DeserializeJsonRecord(_)
+                    | Error::CsvFieldCount { .. }
+                    | Error::InvalidCsvRecord(_)),
+                ) => {
+                    // Rows before the resume point were already reported by the interrupted run
+                    if skip > 0 {
+                        continue;
+                    }
+                    if args.verbose {
+                        eprintln!(
+                            "Failed to read transaction at {}:{}: error: {}",
+                            name,
+                            source.line(),
+                            e
+                        );
+                    }
+                    if let Some(rejects) = &mut rejects {
+                        rejects.write_raw(name, source.line(), source.raw_fields(), &e)?;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let tx = match EngineTransaction::parse_csv_record(&record) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    if skip > 0 {
+                        continue;
+                    }
+                    if args.verbose {
+                        eprintln!(
+                            "Failed to parse transaction at {}:{}: type: {:?}, client: {}, tx: {}, error: {}",
+                            name, record.line, record.r#type, record.client, record.tx, e
+                        );
+                    }
+                    if let Some(rejects) = &mut rejects {
+                        rejects.write_record(name, &record, &e)?;
+                    }
+                    continue;
+                }
+            };
+            // Later transactions are left out entirely, so that a resumed run skips the same ones
+            if args.as_of.is_some() && tx.timestamp() > args.as_of {
+                after_cutoff += 1;
+                continue;
+            }
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let result = match &mut processor {
+                Processor::Single(engine) => engine.process_transaction(tx),
+                Processor::Sharded(engine) => {
+                    engine.submit(((index as u64) << TAG_LINE_BITS) | record.line, tx);
+                    for rejection in engine.rejections() {
+                        report_rejection(args.verbose, &mut rejects, &names, rejection)?;
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                // The engine could not record or apply the transaction, so the run cannot go on
+                if e.category() == ErrorCategory::Io {
+                    return Err(e);
+                }
                 if args.verbose {
                     eprintln!(
-                        "Failed to parse transaction at line {}: type: {:?}, client: {}, tx: {}, error: {}",
-                        line_number, record.r#type, record.client, record.tx, e
+                        "Transaction rejected at {}:{}: type: {:?}, client: {}, tx: {}, error: {}",
+                        name, record.line, record.r#type, record.client, record.tx, e
                     );
                 }
-                continue;
+                if let Some(rejects) = &mut rejects {
+                    rejects.write_record(name, &record, &e)?;
+                }
             }
-        };
-        if let Err(e) = engine.process_transaction(tx) {
-            if args.verbose {
-                eprintln!(
-                    "Transaction rejected at line {}: type: {:?}, client: {}, tx: {}, error: {}",
-                    line_number, record.r#type, record.client, record.tx, e
-                );
+        }
+    }
+
+    let engine = match processor {
+        Processor::Single(engine) => *engine,
+        Processor::Sharded(engine) => {
+            let (engine, rejections) = engine.finish()?;
+            for rejection in rejections {
+                report_rejection(args.verbose, &mut rejects, &names, rejection)?;
             }
+            engine
         }
+    };
+
+    if let Some(rejects) = &mut rejects {
+        rejects.flush()?;
+    }
+    if args.verbose && after_cutoff > 0 {
+        eprintln!(
+            "Left out {} transactions after the --as-of time",
+            after_cutoff
+        );
     }
 
-    let mut w = ::csv::Writer::from_writer(std::io::stdout());
+    Ok(engine)
+}
 
-    for item in engine.output_items() {
-        w.serialize(item).map_err(Error::WriteCsvRecord)?;
+/// Reports a transaction rejected by a worker of the sharded engine, tagged with its input and
+/// line. An I/O error is returned instead, like with a single engine.
+fn report_rejection<W: Write>(
+    verbose: bool,
+    rejects: &mut Option<RejectWriter<W>>,
+    names: &[String],
+    rejection: Rejection,
+) -> Result<(), Error> {
+    if rejection.error.category() == ErrorCategory::Io {
+        return Err(rejection.error);
+    }
+    let transaction = &rejection.transaction;
+    let name = usize::try_from(rejection.tag >> TAG_LINE_BITS)
+        .ok()
+        .and_then(|index| names.get(index))
+        .map_or("", String::as_str);
+    let line = rejection.tag & ((1 << TAG_LINE_BITS) - 1);
+    if verbose {
+        eprintln!(
+            "Transaction rejected at {}:{}: type: {:?}, client: {}, tx: {}, error: {}",
+            name,
+            line,
+            transaction.transaction_type(),
+            transaction.client_id(),
+            transaction.transaction_id(),
+            rejection.error
+        );
+    }
+    if let Some(rejects) = rejects {
+        rejects.write_transaction(name, line, transaction, &rejection.error)?;
     }
-    w.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &Path, journal: &Path, rejects: &Path) {
+        let args = Args::try_parse_from([
+            "coding-test".as_ref(),
+            input.as_os_str(),
+            "--journal".as_ref(),
+            journal.as_os_str(),
+            "--rejects".as_ref(),
+            rejects.as_os_str(),
+        ])
+        .unwrap();
+        process(&args.input, &args.engine, &mut std::io::empty()).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.csv");
+        let journal = dir.path().join("journal.jsonl");
+        let rejects = dir.path().join("rejects.csv");
+        let rows = "type, client, tx, amount\n\
+                    deposit, 1, 1, 10.0\n\
+                    withdrawal, 1, 2, 50.0\n\
+                    deposit, 1, 3, 5.0\n";
+
+        // The interrupted run only got through the first rows
+        std::fs::write(&input, rows).unwrap();
+        run(&input, &journal, &rejects);
+        std::fs::write(
+            &input,
+            format!("{rows}withdrawal, 1, 4, 50.0\ndeposit, 1, 5, 1.0\n"),
+        )
+        .unwrap();
+        run(&input, &journal, &rejects);
+
+        let report = std::fs::read_to_string(&rejects).unwrap();
+        let lines: Vec<_> = report.lines().map(|line| line.split(',').nth(1)).collect();
+        assert_eq!(lines, [Some("line"), Some("3"), Some("5")]);
+
+        // Running the whole input again skips every row up to the last accepted transaction
+        run(&input, &journal, &rejects);
+        assert_eq!(std::fs::read_to_string(&rejects).unwrap(), report);
+    }
+
+    #[test]
+    fn test_rejects_several_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.csv");
+        let second = dir.path().join("second.csv");
+        let rejects = dir.path().join("rejects.csv");
+        std::fs::write(
+            &first,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             withdrawal, 1, 2, 50.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "type, client, tx, amount\n\
+             deposit, 2, 3, 5.0\n\
+             deposit, 2, 1, 5.0\n",
+        )
+        .unwrap();
+        let stdin = "type, client, tx, amount\n\
+                     withdrawal, 1, 4, 1.0\n\
+                     unknown, 2, 5, 1.0\n\
+                     dispute, 2, 1,\n";
+
+        let args = Args::try_parse_from([
+            "coding-test".as_ref(),
+            first.as_os_str(),
+            second.as_os_str(),
+            "-".as_ref(),
+            "--rejects".as_ref(),
+            rejects.as_os_str(),
+        ])
+        .unwrap();
+        let engine = process(&args.input, &args.engine, &mut stdin.as_bytes()).unwrap();
+
+        let mut balances: Vec<_> = engine
+            .output_items()
+            .map(|item| (item.client, item.available.to_string()))
+            .collect();
+        balances.sort();
+        assert_eq!(balances, [(1, "9.0".to_string()), (2, "5.0".to_string())]);
+
+        // The rows of each input are numbered from its own header
+        let report = std::fs::read_to_string(&rejects).unwrap();
+        let rows: Vec<_> = report
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').take(9).collect::<Vec<_>>().join(","))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                format!(
+                    "{},3,withdrawal,1,2,50.0,,,insufficient_funds",
+                    first.display()
+                ),
+                format!(
+                    "{},3,deposit,2,1,5.0,,,transaction_owned_by_another_client",
+                    second.display()
+                ),
+                "<stdin>,3,unknown,2,5,1.0,,,deserialize_csv_record".to_string(),
+                "<stdin>,4,dispute,2,1,,,,transaction_owned_by_another_client".to_string(),
+            ]
+        );
+    }
+}
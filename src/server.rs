@@ -0,0 +1,274 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+use crate::{
+    engine::{COLUMNS, CsvInputRecord, Engine, EngineTransaction},
+    error::Error,
+};
+
+/// The longest line a connection can send, newline included.
+const MAX_LINE_LENGTH: usize = 4096;
+/// How long to wait before accepting connections again after accepting one failed.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections on `listener` and feeds the transactions they send into `engine`, until
+/// `shutdown` completes.
+///
+/// Every connection sends newline-delimited CSV rows with the `type, client, tx, amount` columns
+/// of the input files, optionally followed by `timestamp` and `reason`; a header row and blank
+/// lines are ignored. Each row is answered with one line, in the same order:
+/// - `accepted,<client>,<tx>` when the transaction was applied,
+/// - `rejected,<client>,<tx>,<code>` otherwise, where `code` is [`Error::code`]. `client` and
+///   `tx` are empty if the row could not be read.
+///
+/// A line longer than 4 KiB is rejected with [`Error::LineTooLong`] and closes the connection.
+///
+/// A row is applied before the next row of the same connection is read, so the transactions of
+/// a connection are applied in the order they were sent. Transactions sent on different
+/// connections are applied in the order the server receives them. The engine is locked on a
+/// blocking thread, so journal and record store I/O do not stall the other connections.
+///
+/// Failing to accept a connection, e.g. when the process runs out of file descriptors, is logged
+/// to stderr and accepting resumes shortly after.
+///
+/// When `shutdown` completes, the server stops accepting connections and closes the open ones;
+/// the engine holds every transaction acknowledged so far.
+pub async fn serve(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine>>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Error> {
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(handle_connection(stream, engine.clone()));
+                }
+                Err(e) => {
+                    eprintln!("Failed to accept a connection: {e}");
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+            },
+            // Reap finished connections; an I/O error only closes its own connection
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+
+    connections.shutdown().await;
+    Ok(())
+}
+
+async fn handle_connection(stream: TcpStream, engine: Arc<Mutex<Engine>>) -> Result<(), Error> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut line = String::new();
+    let mut record = csv::StringRecord::new();
+
+    loop {
+        line.clear();
+        let limit = MAX_LINE_LENGTH as u64 + 1;
+        if (&mut reader).take(limit).read_line(&mut line).await? == 0 {
+            break;
+        }
+        if line.len() > MAX_LINE_LENGTH {
+            // The rest of the line cannot be told apart from the next rows
+            let error = Error::LineTooLong(MAX_LINE_LENGTH);
+            writer
+                .write_all(format!("rejected,,,{}\n", error.code()).as_bytes())
+                .await?;
+            break;
+        }
+
+        let ack = match read_transaction(&mut record, &line) {
+            Ok(Some(transaction)) => {
+                let (client, tx) = (transaction.client_id(), transaction.transaction_id());
+                let engine = engine.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    engine
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .process_transaction(transaction)
+                })
+                .await
+                .map_err(std::io::Error::other)?;
+                match result {
+                    Ok(()) => format!("accepted,{client},{tx}\n"),
+                    Err(e) => format!("rejected,{client},{tx},{}\n", e.code()),
+                }
+            }
+            Ok(None) => continue,
+            Err(ack) => ack,
+        };
+        writer.write_all(ack.as_bytes()).await?;
+        // Only wait for the client to read the acknowledgements once it has nothing more queued
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads the transaction of one input line. Returns `None` for a header or blank line, and the
+/// acknowledgement of a line that is not a valid transaction as the error.
+fn read_transaction(
+    record: &mut csv::StringRecord,
+    line: &str,
+) -> Result<Option<EngineTransaction>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let input = match read_record(record, line) {
+        Ok(Some(input)) => input,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(format!("rejected,,,{}\n", e.code())),
+    };
+    EngineTransaction::parse_csv_record(&input)
+        .map(Some)
+        .map_err(|e| format!("rejected,{},{},{}\n", input.client, input.tx, e.code()))
+}
+
+/// Reads a row, or returns `None` for a header row.
+fn read_record<'r>(
+    record: &'r mut csv::StringRecord,
+    line: &str,
+) -> Result<Option<CsvInputRecord<'r>>, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+    if !reader.read_record(record).map_err(Error::read_csv)? {
+        record.clear();
+    }
+    let header = (4..=COLUMNS.len()).contains(&record.len())
+        && record
+            .iter()
+            .zip(COLUMNS)
+            .all(|(field, column)| field == column);
+    if header {
+        return Ok(None);
+    }
+    record
+        .deserialize(None)
+        .map(Some)
+        .map_err(Error::DeserializeCsvRecord)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::CheckedDecimal;
+    use tokio::sync::oneshot;
+
+    async fn start() -> (
+        std::net::SocketAddr,
+        Arc<Mutex<Engine>>,
+        oneshot::Sender<()>,
+        tokio::task::JoinHandle<Result<(), Error>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let engine = Arc::new(Mutex::new(Engine::new()));
+        let (stop, stopped) = oneshot::channel();
+        let server = tokio::spawn(serve(listener, engine.clone(), async {
+            let _ = stopped.await;
+        }));
+        (addr, engine, stop, server)
+    }
+
+    async fn send(addr: std::net::SocketAddr, input: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(input.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).await.unwrap();
+        output
+    }
+
+    #[tokio::test]
+    async fn test_serve_acknowledgements() {
+        let (addr, engine, stop, server) = start().await;
+
+        let output = send(
+            addr,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             \n\
+             withdrawal, 1, 2, 20.0\n\
+             unknown, 1, 3, 1.0\n\
+             type, client, tx, amount, timestamp\n\
+             types, 1, 4, 1.0\n\
+             dispute, 1, 1,\n",
+        )
+        .await;
+        assert_eq!(
+            output,
+            "accepted,1,1\n\
+             rejected,1,2,insufficient_funds\n\
+             rejected,,,deserialize_csv_record\n\
+             rejected,,,deserialize_csv_record\n\
+             accepted,1,1\n"
+        );
+
+        // A line too long closes the connection
+        let output = send(
+            addr,
+            &format!(
+                "deposit, 2, 2, 1.0\n{}\ndeposit, 2, 3, 1.0\n",
+                "1".repeat(5000)
+            ),
+        )
+        .await;
+        assert_eq!(output, "accepted,2,2\nrejected,,,line_too_long\n");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        let engine = engine.lock().unwrap();
+        let balance = engine.account(1).unwrap().balance();
+        assert_eq!(balance.held, CheckedDecimal::parse("10.0").unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_serve_concurrent_connections() {
+        let (addr, engine, stop, server) = start().await;
+
+        // Every connection deposits to its own client, then withdraws more than the deposit
+        // and finally the exact amount: only the per-connection order makes the result exact
+        let mut clients = JoinSet::new();
+        for client in 1..=500u32 {
+            clients.spawn(async move {
+                let input = format!(
+                    "deposit, {client}, {client}, 5.0\n\
+                     withdrawal, {client}, {}, 6.0\n\
+                     withdrawal, {client}, {}, 5.0\n",
+                    100_000 + client,
+                    200_000 + client,
+                );
+                send(addr, &input).await
+            });
+        }
+        while let Some(output) = clients.join_next().await {
+            let output = output.unwrap();
+            let acks: Vec<_> = output.lines().map(|ack| ack.split(',').next()).collect();
+            assert_eq!(acks, [Some("accepted"), Some("rejected"), Some("accepted")]);
+        }
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        let engine = engine.lock().unwrap();
+        assert_eq!(engine.accounts().count(), 500);
+        assert_eq!(engine.transaction_count(), 1500);
+    }
+}